@@ -0,0 +1,95 @@
+//! Compares the table-driven decoder against decoding one bit at a time
+//! through a map from codeword paths to symbols.
+//!
+//! Run with `cargo bench --bench decode`, which fails when the table is not
+//! at least 10 times faster.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rust_huffman_egui::bits::{BitReader, BitWriter};
+use rust_huffman_egui::decode_table::DecodeTable;
+use rust_huffman_egui::huffman::{canonical_codes, MAX_CODE_LENGTH};
+use rust_huffman_egui::package_merge;
+
+const INPUT: &str = "examples/romeo-and-juliet.txt";
+const ROUNDS: u32 = 10;
+/// The least speedup over bit by bit decoding the table has to reach.
+const MIN_SPEEDUP: f64 = 10.0;
+
+fn encode(text: &str, codes: &HashMap<char, (u64, u8)>) -> (Vec<u8>, usize) {
+    let mut writer = BitWriter::new();
+    for c in text.chars() {
+        let (code, len) = codes[&c];
+        writer.write(code, len as u32);
+    }
+    writer.finish()
+}
+
+fn decode_bitwise(paths: &HashMap<Vec<bool>, char>, bytes: &[u8], bit_count: usize) -> String {
+    let mut text = String::new();
+    let mut path = Vec::new();
+    for i in 0..bit_count {
+        path.push((bytes[i / 8] >> (7 - i % 8)) & 1 == 1);
+        if let Some(c) = paths.get(&path) {
+            text.push(*c);
+            path.clear();
+        }
+    }
+    text
+}
+
+fn decode_table(table: &DecodeTable<char>, bytes: &[u8], bit_count: usize) -> String {
+    let mut text = String::new();
+    let mut reader = BitReader::new(bytes);
+    while reader.position() < bit_count {
+        text.push(table.decode(&mut reader).unwrap());
+    }
+    text
+}
+
+fn time(mut f: impl FnMut() -> String) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        black_box(f());
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let text = std::fs::read_to_string(INPUT).expect("Running from the repository root");
+
+    let mut frequencies = BTreeMap::new();
+    for c in text.chars() {
+        *frequencies.entry(c).or_insert(0) += 1;
+    }
+    let weights: Vec<usize> = frequencies.values().copied().collect();
+    let lengths = package_merge::limited_code_lengths(&weights, MAX_CODE_LENGTH);
+    let code_lengths: Vec<(char, u8)> = frequencies.keys().copied().zip(lengths).collect();
+
+    let codes = canonical_codes(&code_lengths);
+    let (bytes, bit_count) = encode(&text, &codes.iter().map(|(c, code, len)| (*c, (*code, *len))).collect());
+
+    let paths: HashMap<Vec<bool>, char> = codes.iter()
+        .map(|(c, code, len)| ((0..*len).rev().map(|offset| (code >> offset) & 1 == 1).collect(), *c))
+        .collect();
+    let table = DecodeTable::new(&code_lengths).expect("Package-merge lengths to fit");
+
+    assert_eq!(decode_bitwise(&paths, &bytes, bit_count), text);
+    assert_eq!(decode_table(&table, &bytes, bit_count), text);
+
+    let bitwise = time(|| decode_bitwise(&paths, &bytes, bit_count));
+    let table = time(|| decode_table(&table, &bytes, bit_count));
+
+    println!("{INPUT}: {} bytes, {} bits coded", text.len(), bit_count);
+    println!("bit by bit map lookups: {:>10.3?}", bitwise);
+    println!("lookup table:           {:>10.3?}", table);
+    let speedup = bitwise.as_secs_f64() / table.as_secs_f64();
+    println!("speedup:                {:>10.1}x", speedup);
+
+    if speedup < MIN_SPEEDUP {
+        eprintln!("the lookup table is less than {MIN_SPEEDUP}x faster");
+        std::process::exit(1);
+    }
+}
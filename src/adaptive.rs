@@ -0,0 +1,259 @@
+//! Adaptive Huffman coding (the FGK algorithm).
+//!
+//! Encoder and decoder start from the same empty tree and update it after
+//! every symbol, so the code follows the statistics of the input as it
+//! arrives and no table is ever stored. A symbol seen for the first time is
+//! sent as the codeword of the NYT ("not yet transmitted") leaf followed by
+//! its value in `Symbol::RAW_BITS` bits.
+//!
+//! The codewords are cut into chunks in the stream, each starting with its
+//! length in bits, and ending with an empty frame.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::bits::{BitReader, BitWriter};
+use crate::huffman::Symbol;
+use crate::stream::{invalid_data, read_frame, write_frame, SymbolDecoder, SymbolEncoder};
+
+/// Packed bytes after which a chunk is written out.
+const CHUNK_BYTES: usize = 1 << 16;
+
+/// Largest chunk a decoder accepts. Codewords of an adaptive tree can get
+/// as long as the number of distinct symbols seen.
+const MAX_CHUNK_BYTES: usize = 1 << 24;
+
+struct Node<S> {
+    weight: u64,
+    parent: Option<usize>,
+    children: Option<(usize, usize)>,
+    symbol: Option<S>,
+    rank: usize // Position in `AdaptiveTree::order`
+}
+
+/// A Huffman tree kept optimal for the symbols seen so far.
+///
+/// Nodes are listed in `order` by decreasing weight, which is the sibling
+/// property: the two children of a node are always next to each other.
+struct AdaptiveTree<S> {
+    nodes: Vec<Node<S>>,
+    order: Vec<usize>,
+    leaves: HashMap<S, usize>,
+    nyt: usize
+}
+
+impl<S: Symbol> AdaptiveTree<S> {
+    fn new() -> Self {
+        let root = Node {
+            weight: 0,
+            parent: None,
+            children: None,
+            symbol: None,
+            rank: 0
+        };
+
+        AdaptiveTree {
+            nodes: vec![root],
+            order: vec![0],
+            leaves: HashMap::new(),
+            nyt: 0
+        }
+    }
+
+    fn push_node(&mut self, parent: usize, symbol: Option<S>) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            weight: 0,
+            parent: Some(parent),
+            children: None,
+            symbol,
+            rank: self.order.len()
+        });
+        self.order.push(id);
+        id
+    }
+
+    /// Bits of the path from the root to `node`, 1 meaning right.
+    fn path(&self, mut node: usize, bits: &mut Vec<bool>) {
+        bits.clear();
+        while let Some(parent) = self.nodes[node].parent {
+            let (_, right) = self.nodes[parent].children.unwrap();
+            bits.push(node == right);
+            node = parent;
+        }
+        bits.reverse();
+    }
+
+    /// The first node in `order` with the same weight as `node`.
+    fn leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let rank = self.order.partition_point(|id| self.nodes[*id].weight > weight);
+        self.order[rank]
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        let parent_a = self.nodes[a].parent.unwrap();
+        let parent_b = self.nodes[b].parent.unwrap();
+
+        if parent_a == parent_b {
+            let (left, right) = self.nodes[parent_a].children.unwrap();
+            self.nodes[parent_a].children = Some((right, left));
+        } else {
+            let replace = |children: (usize, usize), old: usize, new: usize|
+                if children.0 == old { (new, children.1) } else { (children.0, new) };
+            self.nodes[parent_a].children = Some(replace(self.nodes[parent_a].children.unwrap(), a, b));
+            self.nodes[parent_b].children = Some(replace(self.nodes[parent_b].children.unwrap(), b, a));
+            self.nodes[a].parent = Some(parent_b);
+            self.nodes[b].parent = Some(parent_a);
+        }
+
+        let (rank_a, rank_b) = (self.nodes[a].rank, self.nodes[b].rank);
+        self.order.swap(rank_a, rank_b);
+        self.nodes[a].rank = rank_b;
+        self.nodes[b].rank = rank_a;
+    }
+
+    /// Counts one more occurrence of `c`, adding it to the tree if new.
+    fn update(&mut self, c: S) {
+        let mut node = Some(match self.leaves.get(&c) {
+            Some(leaf) => *leaf,
+            None => {
+                // The NYT leaf becomes the parent of a new NYT and of `c`
+                let parent = self.nyt;
+                let leaf = self.push_node(parent, Some(c));
+                let nyt = self.push_node(parent, None);
+                self.nodes[parent].children = Some((nyt, leaf));
+                self.leaves.insert(c, leaf);
+                self.nyt = nyt;
+                leaf
+            }
+        });
+
+        while let Some(q) = node {
+            let leader = self.leader(q);
+
+            if Some(leader) == self.nodes[q].parent {
+                // The sibling of the NYT leaf weighs as much as its parent.
+                // It goes right after the parent and both gain one, so that
+                // `order` stays sorted.
+                let next = self.order[self.nodes[leader].rank + 1];
+                if next != q {
+                    self.swap(q, next);
+                }
+                self.nodes[q].weight += 1;
+                self.nodes[leader].weight += 1;
+                node = self.nodes[leader].parent;
+            } else {
+                if leader != q {
+                    self.swap(q, leader);
+                }
+                self.nodes[q].weight += 1;
+                node = self.nodes[q].parent;
+            }
+        }
+    }
+}
+
+pub struct AdaptiveEncoder<S> {
+    tree: AdaptiveTree<S>,
+    bits: BitWriter,
+    path: Vec<bool>
+}
+
+impl<S: Symbol> AdaptiveEncoder<S> {
+    pub fn new() -> Self {
+        AdaptiveEncoder {
+            tree: AdaptiveTree::new(),
+            bits: BitWriter::new(),
+            path: Vec::new()
+        }
+    }
+
+    fn write_chunk(&mut self, writer: &mut dyn Write) -> io::Result<()> {
+        let (encoded_chars, bit_count) = std::mem::take(&mut self.bits).finish();
+        if bit_count > 0 {
+            write_frame(&Some(bit_count), &mut *writer)?;
+            writer.write_all(&encoded_chars)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Symbol> SymbolEncoder<S> for AdaptiveEncoder<S> {
+    fn push(&mut self, c: S, writer: &mut dyn Write) -> io::Result<()> {
+        let known = self.tree.leaves.get(&c).copied();
+        self.tree.path(known.unwrap_or(self.tree.nyt), &mut self.path);
+        for bit in &self.path {
+            self.bits.write(*bit as u64, 1);
+        }
+        if known.is_none() {
+            self.bits.write(c.to_u32() as u64, S::RAW_BITS);
+        }
+
+        self.tree.update(c);
+
+        if self.bits.byte_len() >= CHUNK_BYTES {
+            self.write_chunk(writer)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, writer: &mut dyn Write) -> io::Result<()> {
+        self.write_chunk(writer)?;
+        write_frame(&None::<usize>, writer)
+    }
+}
+
+pub struct AdaptiveDecoder<S> {
+    tree: AdaptiveTree<S>
+}
+
+impl<S: Symbol> AdaptiveDecoder<S> {
+    pub fn new() -> Self {
+        AdaptiveDecoder { tree: AdaptiveTree::new() }
+    }
+
+    fn decode_symbol(&mut self, reader: &mut BitReader) -> Option<S> {
+        let mut node = 0;
+        while let Some((left, right)) = self.tree.nodes[node].children {
+            node = if reader.peek(1) == 1 { right } else { left };
+            reader.consume(1);
+        }
+
+        let c = match self.tree.nodes[node].symbol {
+            Some(c) => c,
+            None => {
+                let raw = reader.peek(S::RAW_BITS);
+                reader.consume(S::RAW_BITS);
+                S::from_u32(raw as u32)?
+            }
+        };
+
+        self.tree.update(c);
+        Some(c)
+    }
+}
+
+impl<S: Symbol> SymbolDecoder for AdaptiveDecoder<S> {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let Some(bit_count): Option<usize> = read_frame(&mut *reader)? else {
+            return Ok(false)
+        };
+
+        let len = bit_count.div_ceil(8);
+        if len > MAX_CHUNK_BYTES {
+            return Err(invalid_data(format!("Chunk of {len} bytes is too long")))
+        }
+        let mut encoded_chars = vec![0; len];
+        reader.read_exact(&mut encoded_chars)?;
+
+        let mut bits = BitReader::new(&encoded_chars);
+        while bits.position() < bit_count {
+            let c = self.decode_symbol(&mut bits)
+                .ok_or_else(|| invalid_data("Invalid symbol in compressed stream"))?;
+            c.push_to(out);
+        }
+
+        Ok(true)
+    }
+}
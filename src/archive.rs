@@ -0,0 +1,462 @@
+//! Archives holding a whole directory tree.
+//!
+//! | Bytes | Contents                                                  |
+//! |-------|-----------------------------------------------------------|
+//! | 4     | Magic number, `HUA` followed by `0x1A`                    |
+//! | 1     | Format version, currently 1                               |
+//! | ...   | Shared code, if any: a postcard `Option` of the code      |
+//! |       | lengths, as in `code_lengths_to_u32`                      |
+//! | ...   | Entries, each a postcard `Some` of its `ArchiveEntry`,    |
+//! |       | then for files the payload length (8 bytes, little        |
+//! |       | endian) and the payload                                   |
+//! | 1     | A postcard `None` marking the end                         |
+//!
+//! A payload is a compressed stream of the file contents (see `stream`),
+//! coded with the shared code when the archive has one, or stored when
+//! coding does not pay off. Entry paths are relative, with `/` between
+//! components, and directories come before their contents.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::container::FileInfo;
+use crate::error::HuffmanError;
+use crate::huffman::{code_lengths_for, code_lengths_from_u32, code_lengths_to_u32,
+                     CodeLengths, CodingMethod, Frequencies, Huffman, HuffmanOptions, SymbolMode};
+use crate::stream::{invalid_data, max_compressed_len, read_frame, write_frame, HuffmanDecoder, HuffmanEncoder};
+
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"HUA\x1a";
+
+/// Version of the archive format written, the only one read back.
+pub const ARCHIVE_VERSION: u8 = 1;
+
+pub const ARCHIVE_FILE_EXTENSION: &str = "huffa";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryKind {
+    File,
+    Directory
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    /// Size of the original file, 0 for directories
+    pub len: u64,
+    /// Seconds since the Unix epoch
+    pub modified: Option<i64>,
+    /// Unix permission bits
+    pub permissions: Option<u32>,
+    /// Size of the payload in the archive, 0 for directories
+    #[serde(skip)]
+    pub compressed_len: u64
+}
+
+impl ArchiveEntry {
+    fn info(&self) -> FileInfo {
+        FileInfo {
+            len: Some(self.len),
+            name: None,
+            modified: self.modified,
+            permissions: self.permissions
+        }
+    }
+
+    /// Where the entry goes when extracting into `destination`, refusing
+    /// paths that would lead outside of it.
+    fn destination(&self, destination: &Path) -> io::Result<PathBuf> {
+        let relative = Path::new(&self.path);
+        let plain = !self.path.is_empty()
+            && relative.components().all(|component| matches!(component, Component::Normal(_)));
+        if !plain {
+            return Err(invalid_data(format!("Unsafe path in archive: {}", self.path)))
+        }
+        Ok(destination.join(relative))
+    }
+}
+
+/// Files and directories under `dir`, the directory itself first and
+/// every listing sorted by name so that archives are reproducible.
+fn walk(dir: &Path, relative: &str, entries: &mut Vec<(PathBuf, String)>) -> io::Result<()> {
+    entries.push((dir.to_path_buf(), relative.to_string()));
+
+    let mut children: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let name = child.file_name().into_string()
+            .map_err(|name| invalid_data(format!("File name is not valid UTF-8: {name:?}")))?;
+        let path = child.path();
+        let relative = format!("{relative}/{name}");
+
+        let file_type = child.file_type()?;
+        if file_type.is_dir() {
+            walk(&path, &relative, entries)?;
+        } else if file_type.is_file() {
+            entries.push((path, relative));
+        }
+        // Symbolic links and other special files are left out
+    }
+
+    Ok(())
+}
+
+/// Counts the bytes of every file, for a code shared by all of them.
+fn shared_code(paths: &[&Path], options: HuffmanOptions) -> io::Result<CodeLengths<u8>> {
+    let mut counts = [0usize; 256];
+    let mut buffer = vec![0; 1 << 16];
+
+    for path in paths {
+        let mut file = File::open(path)?;
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break
+            }
+            for b in &buffer[..n] {
+                counts[*b as usize] += 1;
+            }
+        }
+    }
+
+    let frequencies: Frequencies<u8> = (0..=255u8)
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    Ok(code_lengths_for(&frequencies, options))
+}
+
+/// Compresses `dir` and everything under it into a new archive at
+/// `archive_path`. With `share_code`, all files are coded as bytes with a
+/// single Huffman code stored once, whatever `options.coder`, which pays
+/// off for many small files.
+pub fn create_archive(dir: &Path, archive_path: &Path, options: HuffmanOptions,
+                      share_code: bool) -> Result<Vec<ArchiveEntry>, HuffmanError> {
+    let root = dir.file_name().and_then(|name| name.to_str())
+        .ok_or_else(|| invalid_data("Directory has no usable name"))?;
+
+    let mut paths = Vec::new();
+    walk(dir, root, &mut paths)?;
+
+    let shared = if share_code {
+        let files: Vec<&Path> = paths.iter()
+            .map(|(path, _)| path.as_path())
+            .filter(|path| path.is_file())
+            .collect();
+        Some(shared_code(&files, options)?)
+    } else {
+        None
+    };
+
+    let mut writer = BufWriter::new(File::create(archive_path)?);
+    writer.write_all(&ARCHIVE_MAGIC)?;
+    writer.write_all(&[ARCHIVE_VERSION])?;
+    write_frame(&shared.as_ref().map(|code| code_lengths_to_u32(code)), &mut writer)?;
+
+    let mut entries = Vec::with_capacity(paths.len());
+    for (path, relative) in paths {
+        let metadata = fs::metadata(&path)?;
+        let info = FileInfo::of_file(&path, &metadata);
+
+        let mut entry = ArchiveEntry {
+            path: relative,
+            kind: if metadata.is_dir() { EntryKind::Directory } else { EntryKind::File },
+            len: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: options.store_metadata.then_some(info.modified).flatten(),
+            permissions: options.store_metadata.then_some(info.permissions).flatten(),
+            compressed_len: 0
+        };
+        write_frame(&Some(&entry), &mut writer)?;
+
+        if entry.kind == EntryKind::File {
+            entry.compressed_len = write_payload(&mut writer, &path, entry.len, options, shared.as_ref())?;
+        }
+        entries.push(entry);
+    }
+
+    write_frame(&None::<ArchiveEntry>, &mut writer)?;
+
+    // Payloads that fell back to being stored may have left longer coded
+    // attempts behind them
+    let end = writer.stream_position()?;
+    writer.into_inner().map_err(|err| err.into_error())?.set_len(end)?;
+
+    Ok(entries)
+}
+
+/// Writes the length of the compressed file and then the file itself,
+/// stored as is if coding made it bigger, returning that length.
+fn write_payload(mut writer: impl Write + Seek, path: &Path, len: u64, options: HuffmanOptions,
+                 shared: Option<&CodeLengths<u8>>) -> io::Result<u64> {
+    let length_position = writer.stream_position()?;
+    writer.write_all(&0u64.to_le_bytes())?;
+    let payload_position = writer.stream_position()?;
+
+    let info = FileInfo { len: Some(len), ..FileInfo::default() };
+    let max_len = max_compressed_len(len, &info);
+
+    let mut input = File::open(path)?;
+    let mut encoder = match shared {
+        Some(code) => HuffmanEncoder::with_shared_code(&mut writer, info.clone(), options, code)?,
+        None => {
+            let mode = Huffman::from_reader(BufReader::new(&input), options).map_err(HuffmanError::into_io)?.mode();
+            input.rewind()?;
+            HuffmanEncoder::new(&mut writer, mode, info.clone(), options)?
+        }
+    };
+    io::copy(&mut BufReader::new(&input), &mut encoder)?;
+    encoder.finish()?;
+
+    if writer.stream_position()? - payload_position > max_len {
+        writer.seek(SeekFrom::Start(payload_position))?;
+        input.rewind()?;
+
+        let stored = HuffmanOptions { method: CodingMethod::Stored, ..options };
+        let mut encoder = HuffmanEncoder::new(&mut writer, SymbolMode::Binary, info, stored)?;
+        io::copy(&mut BufReader::new(&input), &mut encoder)?;
+        encoder.finish()?;
+    }
+
+    let end = writer.stream_position()?;
+    let compressed_len = end - payload_position;
+    writer.seek(SeekFrom::Start(length_position))?;
+    writer.write_all(&compressed_len.to_le_bytes())?;
+    writer.seek(SeekFrom::Start(end))?;
+
+    Ok(compressed_len)
+}
+
+/// Reads the entries of an archive one after the other.
+pub struct ArchiveReader {
+    reader: BufReader<File>,
+    shared: Option<CodeLengths<u8>>
+}
+
+impl ArchiveReader {
+    pub fn open(archive_path: &Path) -> Result<Self, HuffmanError> {
+        let mut reader = BufReader::new(File::open(archive_path)?);
+
+        let mut magic_and_version = [0; 5];
+        reader.read_exact(&mut magic_and_version)?;
+        if magic_and_version[..4] != ARCHIVE_MAGIC {
+            return Err(HuffmanError::Format("Not an archive (wrong magic number)".to_owned()))
+        }
+        let version = magic_and_version[4];
+        if version != ARCHIVE_VERSION {
+            return Err(HuffmanError::UnsupportedVersion { found: version, supported: ARCHIVE_VERSION })
+        }
+
+        let shared: Option<Vec<(u32, u8)>> = read_frame(&mut reader)?;
+        let shared = shared
+            .map(|code| code_lengths_from_u32(&code).ok_or_else(|| invalid_data("Invalid shared code")))
+            .transpose()?;
+
+        Ok(ArchiveReader { reader, shared })
+    }
+
+    /// The next entry, with the reader left at its payload.
+    fn next_entry(&mut self) -> io::Result<Option<ArchiveEntry>> {
+        let Some(mut entry): Option<ArchiveEntry> = read_frame(&mut self.reader)? else {
+            return Ok(None)
+        };
+        if entry.kind == EntryKind::File {
+            let mut len = [0; 8];
+            self.reader.read_exact(&mut len)?;
+            entry.compressed_len = u64::from_le_bytes(len);
+        }
+        Ok(Some(entry))
+    }
+
+    fn skip_payload(&mut self, entry: &ArchiveEntry) -> io::Result<()> {
+        let len = i64::try_from(entry.compressed_len)
+            .map_err(|_| invalid_data("Payload is too long"))?;
+        self.reader.seek_relative(len)
+    }
+
+    /// Decompresses the payload the reader is at into `destination`, which
+    /// must not exist yet.
+    fn extract_payload(&mut self, entry: &ArchiveEntry, destination: &Path,
+                       restore_metadata: bool) -> io::Result<()> {
+        let mut payload = (&mut self.reader).take(entry.compressed_len);
+        let mut decoder = match &self.shared {
+            Some(code) => HuffmanDecoder::with_shared_code(&mut payload, code)?,
+            None => HuffmanDecoder::new(&mut payload)?
+        };
+
+        let mut output = BufWriter::new(File::create_new(destination)?);
+        let extracted = io::copy(&mut decoder, &mut output)
+            .and_then(|_| output.into_inner().map_err(|err| err.into_error()))
+            .and_then(|output| if restore_metadata { entry.info().apply_to(&output) } else { Ok(()) });
+        if extracted.is_err() {
+            let _ = fs::remove_file(destination);
+        }
+        extracted?;
+
+        // Whatever the stream did not need, so that the next entry follows
+        io::copy(&mut payload, &mut io::sink())?;
+        Ok(())
+    }
+
+    pub fn entries(mut self) -> Result<Vec<ArchiveEntry>, HuffmanError> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.next_entry()? {
+            self.skip_payload(&entry)?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Extracts every entry under `destination`, without overwriting
+    /// existing files.
+    pub fn extract_all(mut self, destination: &Path, restore_metadata: bool) -> Result<(), HuffmanError> {
+        let mut directories = Vec::new();
+
+        while let Some(entry) = self.next_entry()? {
+            let path = entry.destination(destination)?;
+            match entry.kind {
+                EntryKind::Directory => {
+                    fs::create_dir_all(&path)?;
+                    directories.push((entry, path));
+                },
+                EntryKind::File => self.extract_payload(&entry, &path, restore_metadata)?
+            }
+        }
+
+        // Last, and innermost first, so that read-only directories can
+        // still be filled and their times are not updated afterwards
+        if restore_metadata {
+            for (entry, path) in directories.iter().rev() {
+                entry.info().apply_to(&File::open(path)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Extracts the file at `entry_path` in the archive into the
+    /// `destination` directory, returning where it was saved.
+    pub fn extract_entry(mut self, entry_path: &str, destination: &Path,
+                         restore_metadata: bool) -> Result<PathBuf, HuffmanError> {
+        while let Some(entry) = self.next_entry()? {
+            if entry.path == entry_path && entry.kind == EntryKind::File {
+                let name = Path::new(&entry.path).file_name()
+                    .ok_or_else(|| invalid_data(format!("Unsafe path in archive: {}", entry.path)))?;
+                let path = destination.join(name);
+                self.extract_payload(&entry, &path, restore_metadata)?;
+                return Ok(path)
+            }
+            self.skip_payload(&entry)?;
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("No file {entry_path} in the archive")).into())
+    }
+}
+
+/// Whether the file at `path` starts like an archive.
+pub fn is_archive(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == ARCHIVE_MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-huffman-egui-archive-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(path: &str, len: u64) -> ArchiveEntry {
+        ArchiveEntry { path: path.to_string(), kind: EntryKind::File, len, modified: None, permissions: None,
+                       compressed_len: 0 }
+    }
+
+    /// An archive at `path` holding `files`, whatever their paths, as a
+    /// crafted archive could.
+    fn write_archive(path: &Path, files: &[(&str, &[u8])]) {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        writer.write_all(&ARCHIVE_MAGIC).unwrap();
+        writer.write_all(&[ARCHIVE_VERSION]).unwrap();
+        write_frame(&None::<Vec<(u32, u8)>>, &mut writer).unwrap();
+
+        for (file_path, contents) in files {
+            write_frame(&Some(entry(file_path, contents.len() as u64)), &mut writer).unwrap();
+            let payload = Huffman::compress_bytes(contents, HuffmanOptions::default()).unwrap();
+            writer.write_all(&(payload.len() as u64).to_le_bytes()).unwrap();
+            writer.write_all(&payload).unwrap();
+        }
+
+        write_frame(&None::<ArchiveEntry>, &mut writer).unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn only_plain_relative_paths_have_a_destination() {
+        let destination = Path::new("extracted");
+        assert_eq!(entry("tree/sub/file.txt", 0).destination(destination).unwrap(),
+                   destination.join("tree").join("sub").join("file.txt"));
+
+        for path in ["../x", "tree/../../x", "./x", "/x", "/tmp/x", ""] {
+            let err = entry(path, 0).destination(destination).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{path:?}");
+        }
+    }
+
+    #[test]
+    fn extraction_refuses_unsafe_paths() {
+        let dir = scratch_dir("unsafe");
+        let archive_path = dir.join("crafted.huffa");
+        let extracted = dir.join("extracted");
+        let outside = dir.join("x");
+
+        for path in ["../x".to_string(), outside.to_str().unwrap().to_string(), String::new()] {
+            write_archive(&archive_path, &[(&path, b"escaped")]);
+            fs::create_dir_all(&extracted).unwrap();
+
+            let err = ArchiveReader::open(&archive_path).unwrap().extract_all(&extracted, false).unwrap_err();
+            assert!(matches!(err, HuffmanError::Format(_)), "{path:?}: {err:?}");
+            assert!(!outside.exists(), "{path:?} was extracted outside");
+            assert_eq!(fs::read_dir(&extracted).unwrap().count(), 0, "{path:?} was extracted");
+
+            fs::remove_dir_all(&extracted).unwrap();
+        }
+
+        // A single entry is saved under its file name alone
+        write_archive(&archive_path, &[("../x", b"escaped")]);
+        fs::create_dir_all(&extracted).unwrap();
+        let saved = ArchiveReader::open(&archive_path).unwrap().extract_entry("../x", &extracted, false).unwrap();
+        assert_eq!(saved, extracted.join("x"));
+        assert!(!outside.exists());
+
+        write_archive(&archive_path, &[("..", b"escaped")]);
+        assert!(ArchiveReader::open(&archive_path).unwrap().extract_entry("..", &extracted, false).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extraction_never_overwrites() {
+        let dir = scratch_dir("overwrite");
+        let archive_path = dir.join("crafted.huffa");
+        write_archive(&archive_path, &[("x", b"from the archive")]);
+
+        let existing = dir.join("x");
+        fs::write(&existing, "already there").unwrap();
+
+        let err = ArchiveReader::open(&archive_path).unwrap().extract_all(&dir, false).unwrap_err();
+        assert!(matches!(&err, HuffmanError::Io(err) if err.kind() == io::ErrorKind::AlreadyExists), "{err:?}");
+        assert!(ArchiveReader::open(&archive_path).unwrap().extract_entry("x", &dir, false).is_err());
+
+        // Neither failure removes or changes what was there
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "already there");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+/// Reads a packed bit stream most significant bit first, which is the
+/// order codewords are written in. Reading past the end yields zeros, like
+/// the padding at the end of the last byte.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    next_byte: usize,
+    buffer: u64, // Bits not consumed yet, aligned to the left
+    buffered: u32,
+    consumed: usize
+}
+
+impl<'a> BitReader<'a> {
+    /// Widest value `peek` can return.
+    pub const MAX_PEEK: u32 = 56;
+
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            next_byte: 0,
+            buffer: 0,
+            buffered: 0,
+            consumed: 0
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.buffered <= 56 {
+            let byte = self.data.get(self.next_byte).copied().unwrap_or(0);
+            self.buffer |= (byte as u64) << (56 - self.buffered);
+            self.buffered += 8;
+            self.next_byte += 1;
+        }
+    }
+
+    /// The next `n` bits, without consuming them.
+    pub fn peek(&mut self, n: u32) -> u64 {
+        debug_assert!(n <= Self::MAX_PEEK);
+        if n == 0 {
+            return 0
+        }
+        if self.buffered < n {
+            self.refill();
+        }
+        self.buffer >> (64 - n)
+    }
+
+    pub fn consume(&mut self, n: u32) {
+        debug_assert!(n <= self.buffered);
+        self.buffer = self.buffer.checked_shl(n).unwrap_or(0);
+        self.buffered -= n;
+        self.consumed += n as usize;
+    }
+
+    /// Number of bits consumed so far.
+    pub fn position(&self) -> usize {
+        self.consumed
+    }
+}
+
+/// Packs codewords into bytes most significant bit first, padding the last
+/// byte with zeros.
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64, // Pending bits, aligned to the right
+    buffered: u32,
+    written: usize
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter::default()
+    }
+
+    /// Appends the lowest `len` bits of `bits`, `len` being at most 32.
+    pub fn write(&mut self, bits: u64, len: u32) {
+        debug_assert!(len <= 32);
+        self.buffer = (self.buffer << len) | bits;
+        self.buffered += len;
+        self.written += len as usize;
+
+        while self.buffered >= 8 {
+            self.buffered -= 8;
+            self.bytes.push((self.buffer >> self.buffered) as u8);
+        }
+    }
+
+    /// Number of whole bytes packed so far.
+    pub fn byte_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// The packed bytes and the number of bits written to them.
+    pub fn finish(mut self) -> (Vec<u8>, usize) {
+        if self.buffered > 0 {
+            self.bytes.push((self.buffer << (8 - self.buffered)) as u8);
+        }
+        (self.bytes, self.written)
+    }
+}
+
+/// Packs bits into bytes least significant bit first, the order of
+/// DEFLATE. Whole bytes can be taken out as they are packed.
+#[derive(Default)]
+pub struct LsbBitWriter {
+    bytes: Vec<u8>,
+    buffer: u64, // Pending bits, the first at the bottom
+    buffered: u32
+}
+
+impl LsbBitWriter {
+    pub fn new() -> Self {
+        LsbBitWriter::default()
+    }
+
+    /// Appends the lowest `len` bits of `bits`, lowest first, `len` being
+    /// at most 32.
+    pub fn write(&mut self, bits: u64, len: u32) {
+        debug_assert!(len <= 32);
+        self.buffer |= (bits & ((1 << len) - 1)) << self.buffered;
+        self.buffered += len;
+
+        while self.buffered >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.buffered -= 8;
+        }
+    }
+
+    /// Appends a codeword, whose most significant bit comes first.
+    pub fn write_code(&mut self, code: u64, len: u32) {
+        debug_assert!(len > 0);
+        self.write(code.reverse_bits() >> (64 - len), len);
+    }
+
+    /// Pads with zeros up to the next byte boundary.
+    pub fn align(&mut self) {
+        if self.buffered > 0 {
+            self.write(0, 8 - self.buffered);
+        }
+    }
+
+    /// Appends whole bytes, the writer being aligned.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        debug_assert_eq!(self.buffered, 0);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Takes the bytes packed so far, leaving the bits of an unfinished one.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
@@ -0,0 +1,335 @@
+//! Blocks of a compressed stream.
+//!
+//! The input is cut into blocks of `BLOCK_SYMBOLS` symbols, each coded with
+//! a canonical code built from its own frequencies. When the code of the
+//! previous block does at least as well, the block reuses it instead of
+//! paying for a new table.
+//!
+//! Blocks are coded and decoded in batches spread over several threads.
+//! Only the choice of table depends on the block before, and it is made in
+//! order, so the output is the same whatever the number of threads.
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+
+use crate::bits::{BitReader, BitWriter};
+use crate::decode_table::DecodeTable;
+use crate::huffman::{canonical_codewords, code_lengths_for, code_lengths_from_u32, code_lengths_to_u32,
+                     CodeLengths, Frequencies, HuffmanOptions, Symbol, MAX_CODE_LENGTH};
+use crate::parallel;
+use crate::stream::{invalid_data, read_frame, write_frame, SymbolDecoder, SymbolEncoder};
+
+/// Number of symbols in every block but the last.
+pub const BLOCK_SYMBOLS: usize = 1 << 16;
+
+/// Largest payload a block can have, with every symbol using the longest
+/// codeword possible.
+const MAX_BLOCK_BYTES: usize = BLOCK_SYMBOLS * MAX_CODE_LENGTH as usize / 8;
+
+#[derive(Serialize, Deserialize)]
+pub enum BlockTable {
+    /// Code lengths of the symbols in the block, as in `code_lengths_to_u32`
+    New(Vec<(u32, u8)>),
+    /// The block is coded like the one before it
+    Reuse
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockHeader {
+    table: BlockTable,
+    bit_count: usize
+}
+
+/// A block as written to the stream: its header, then its codewords packed
+/// into whole bytes.
+pub struct EncodedBlock {
+    header: BlockHeader,
+    encoded_chars: Vec<u8>
+}
+
+impl EncodedBlock {
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        write_frame(&Some(&self.header), &mut writer)?;
+        writer.write_all(&self.encoded_chars)
+    }
+
+    pub fn write_end(writer: impl Write) -> io::Result<()> {
+        write_frame(&None::<BlockHeader>, writer)
+    }
+
+    /// The next block, or `None` at the end of the stream.
+    pub fn read_from(mut reader: impl Read) -> io::Result<Option<Self>> {
+        let Some(header): Option<BlockHeader> = read_frame(&mut reader)? else {
+            return Ok(None)
+        };
+
+        let len = header.bit_count.div_ceil(8);
+        if len > MAX_BLOCK_BYTES {
+            return Err(invalid_data(format!("Block of {len} bytes is too long")))
+        }
+
+        let mut encoded_chars = vec![0; len];
+        reader.read_exact(&mut encoded_chars)?;
+
+        Ok(Some(EncodedBlock { header, encoded_chars }))
+    }
+}
+
+pub fn count<S: Symbol>(symbols: &[S]) -> Frequencies<S> {
+    let mut frequencies = Frequencies::new();
+    for c in symbols {
+        *frequencies.entry(*c).or_insert(0) += 1;
+    }
+    frequencies
+}
+
+/// Bits needed to code the symbols counted in `frequencies`, or `None` if
+/// some of them have no codeword.
+fn coded_bits<S: Symbol>(frequencies: &Frequencies<S>, code_lengths: &CodeLengths<S>) -> Option<usize> {
+    frequencies.iter()
+        .map(|(c, freq)| {
+            let i = code_lengths.binary_search_by_key(c, |(s, _)| *s).ok()?;
+            Some(freq * code_lengths[i].1 as usize)
+        })
+        .sum()
+}
+
+/// Picks the cheaper way of coding a block with the given frequencies:
+/// with `code_lengths`, its own code, or with the previous block's code.
+pub fn choose_table<S: Symbol>(frequencies: &Frequencies<S>,
+                               code_lengths: CodeLengths<S>,
+                               previous: Option<&CodeLengths<S>>) -> (BlockTable, CodeLengths<S>) {
+    let table = BlockTable::New(code_lengths_to_u32(&code_lengths));
+
+    let table_bits = 8 * postcard::to_allocvec(&table).expect("A valid serialisation").len();
+    let new_cost = table_bits + coded_bits(frequencies, &code_lengths).unwrap();
+
+    if let Some(previous) = previous {
+        let reuse_cost = coded_bits(frequencies, previous).map(|bits| bits + 8);
+        if reuse_cost.is_some_and(|cost| cost <= new_cost) {
+            return (BlockTable::Reuse, previous.clone())
+        }
+    }
+
+    (table, code_lengths)
+}
+
+/// Packs the codewords of `symbols`, returning the bytes and bit count.
+pub fn pack<S: Symbol>(symbols: &[S], code_lengths: &CodeLengths<S>) -> (Vec<u8>, usize) {
+    let codewords = canonical_codewords(code_lengths);
+
+    let mut writer = BitWriter::new();
+    for c in symbols {
+        let (bits, len) = codewords[c];
+        writer.write(bits, len as u32);
+    }
+
+    writer.finish()
+}
+
+/// Codes a block of symbols on its own, for `BufferedEncoder`.
+pub trait BlockCoder<S> {
+    type Header: Serialize;
+
+    /// Symbols in every block but the last.
+    const BLOCK_LEN: usize;
+
+    /// The header of the block, and its payload.
+    fn code_block(&self, symbols: &[S]) -> io::Result<(Self::Header, Vec<u8>)>;
+}
+
+/// Gathers symbols into blocks and has `C` code each once it is full.
+/// A block is written as a postcard `Some` of its header followed by its
+/// payload, and a `None` marks the end of the stream.
+pub struct BufferedEncoder<S, C> {
+    symbols: Vec<S>,
+    coder: C
+}
+
+impl<S: Symbol, C: BlockCoder<S>> BufferedEncoder<S, C> {
+    pub fn new(coder: C) -> Self {
+        BufferedEncoder { symbols: Vec::with_capacity(C::BLOCK_LEN), coder }
+    }
+
+    fn write_block(&mut self, writer: &mut dyn Write) -> io::Result<()> {
+        let (header, bytes) = self.coder.code_block(&self.symbols)?;
+        write_frame(&Some(header), &mut *writer)?;
+        writer.write_all(&bytes)?;
+
+        self.symbols.clear();
+        Ok(())
+    }
+}
+
+impl<S: Symbol, C: BlockCoder<S>> SymbolEncoder<S> for BufferedEncoder<S, C> {
+    fn push(&mut self, c: S, writer: &mut dyn Write) -> io::Result<()> {
+        self.symbols.push(c);
+        if self.symbols.len() == C::BLOCK_LEN {
+            self.write_block(writer)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, writer: &mut dyn Write) -> io::Result<()> {
+        if !self.symbols.is_empty() {
+            self.write_block(writer)?;
+        }
+        write_frame(&None::<C::Header>, writer)
+    }
+}
+
+/// Gathers symbols into batches of blocks and codes each batch once it
+/// is full.
+pub struct BlockEncoder<S> {
+    symbols: Vec<S>,
+    previous: Option<CodeLengths<S>>,
+    options: HuffmanOptions,
+    threads: usize
+}
+
+impl<S: Symbol> BlockEncoder<S> {
+    pub fn new(options: HuffmanOptions) -> Self {
+        let threads = parallel::threads(options.threads);
+        BlockEncoder {
+            symbols: Vec::with_capacity(threads * BLOCK_SYMBOLS),
+            previous: None,
+            options,
+            threads
+        }
+    }
+
+    /// An encoder whose first block may reuse `code_lengths`.
+    pub fn with_code(options: HuffmanOptions, code_lengths: CodeLengths<S>) -> Self {
+        BlockEncoder {
+            previous: Some(code_lengths),
+            ..Self::new(options)
+        }
+    }
+
+    fn encode_blocks(&mut self, writer: &mut dyn Write) -> io::Result<()> {
+        let blocks: Vec<&[S]> = self.symbols.chunks(BLOCK_SYMBOLS).collect();
+
+        let codes = parallel::map(&blocks, self.threads, |symbols| {
+            let frequencies = count(symbols);
+            let code_lengths = code_lengths_for(&frequencies, self.options);
+            (frequencies, code_lengths)
+        });
+
+        let mut tables = Vec::with_capacity(blocks.len());
+        for (frequencies, code_lengths) in codes {
+            let (table, code_lengths) = choose_table(&frequencies, code_lengths, self.previous.as_ref());
+            self.previous = Some(code_lengths.clone());
+            tables.push((table, code_lengths));
+        }
+
+        let jobs: Vec<_> = blocks.into_iter().zip(tables).collect();
+        let encoded_blocks = parallel::map(&jobs, self.threads, |(symbols, (_, code_lengths))| {
+            pack(symbols, code_lengths)
+        });
+
+        for ((_, (table, _)), (encoded_chars, bit_count)) in jobs.into_iter().zip(encoded_blocks) {
+            EncodedBlock {
+                header: BlockHeader { table, bit_count },
+                encoded_chars
+            }.write_to(&mut *writer)?;
+        }
+
+        self.symbols.clear();
+        Ok(())
+    }
+}
+
+impl<S: Symbol> SymbolEncoder<S> for BlockEncoder<S> {
+    fn push(&mut self, c: S, writer: &mut dyn Write) -> io::Result<()> {
+        self.symbols.push(c);
+        if self.symbols.len() == self.threads * BLOCK_SYMBOLS {
+            self.encode_blocks(writer)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, writer: &mut dyn Write) -> io::Result<()> {
+        self.encode_blocks(writer)?;
+        EncodedBlock::write_end(writer)
+    }
+}
+
+/// Reads blocks in batches, resolving which code each one uses in order,
+/// and decodes the blocks of a batch in parallel.
+pub struct BlockDecoder<S> {
+    table: Option<Arc<DecodeTable<S>>>,
+    threads: usize,
+    finished: bool
+}
+
+impl<S: Symbol> BlockDecoder<S> {
+    pub fn new(threads: usize) -> Self {
+        BlockDecoder {
+            table: None,
+            threads: parallel::threads(threads),
+            finished: false
+        }
+    }
+
+    /// A decoder for blocks coded by `BlockEncoder::with_code`.
+    pub fn with_code(threads: usize, code_lengths: &CodeLengths<S>) -> io::Result<Self> {
+        Ok(BlockDecoder {
+            table: Some(Arc::new(DecodeTable::new(code_lengths)?)),
+            ..Self::new(threads)
+        })
+    }
+
+    /// The next block and the code it uses, or `None` at the end.
+    fn read_block(&mut self, reader: &mut dyn Read) -> io::Result<Option<(EncodedBlock, Arc<DecodeTable<S>>)>> {
+        let Some(block) = EncodedBlock::read_from(reader)? else {
+            self.finished = true;
+            return Ok(None)
+        };
+
+        if let BlockTable::New(code_lengths) = &block.header.table {
+            let code_lengths = code_lengths_from_u32::<S>(code_lengths)
+                .ok_or_else(|| invalid_data("Invalid code lengths"))?;
+            self.table = Some(Arc::new(DecodeTable::new(&code_lengths)?));
+        }
+
+        let table = self.table.clone()
+            .ok_or_else(|| invalid_data("First block has no code table"))?;
+        Ok(Some((block, table)))
+    }
+}
+
+/// Decodes every symbol of the block.
+fn decode_block<S: Symbol>(block: &EncodedBlock, table: &DecodeTable<S>) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(BLOCK_SYMBOLS);
+    let mut reader = BitReader::new(&block.encoded_chars);
+    while reader.position() < block.header.bit_count {
+        let c = table.decode(&mut reader)
+            .ok_or_else(|| invalid_data("Invalid codeword in compressed stream"))?;
+        c.push_to(&mut out);
+    }
+    Ok(out)
+}
+
+impl<S: Symbol> SymbolDecoder for BlockDecoder<S> {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let mut batch = Vec::with_capacity(self.threads);
+        while !self.finished && batch.len() < self.threads {
+            if let Some(block) = self.read_block(&mut *reader)? {
+                batch.push(block);
+            }
+        }
+
+        if batch.is_empty() {
+            return Ok(false)
+        }
+
+        let decoded = parallel::map(&batch, self.threads, |(block, table)| decode_block(block, table));
+        for symbols in decoded {
+            out.extend_from_slice(&symbols?);
+        }
+
+        Ok(true)
+    }
+}
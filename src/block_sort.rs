@@ -0,0 +1,131 @@
+//! Block sorting, the scheme of bzip2: the Burrows-Wheeler transform, then
+//! move-to-front and run-length coding of zeros (see `transform`), and
+//! Huffman coding of what comes out.
+//!
+//! The input is cut into blocks of `SORT_BLOCK_BYTES` bytes, each
+//! transformed on its own and Huffman coded with a code of its own. The
+//! header of a block holds the code lengths, and the coded symbols follow.
+
+use std::io::{self, Read};
+
+use serde::{Serialize, Deserialize};
+
+use crate::bits::{BitReader, BitWriter};
+use crate::block::BlockCoder;
+use crate::decode_table::DecodeTable;
+use crate::huffman::{canonical_codewords, code_lengths_for, code_lengths_from_u32, code_lengths_to_u32,
+                     CodeLengths, Frequencies, HuffmanOptions, MAX_CODE_LENGTH};
+use crate::stream::{invalid_data, read_frame, SymbolDecoder};
+use crate::transform::{Bwt, MoveToFront, Pipeline, Stage, ZeroRunLength};
+
+/// Bytes in every block but the last, as in bzip2 at its best.
+pub const SORT_BLOCK_BYTES: usize = 900_000;
+
+/// Most symbols the transforms can turn a block into: zero runs only
+/// shorten it, and the BWT adds its index.
+const MAX_BLOCK_SYMBOLS: usize = SORT_BLOCK_BYTES + 4;
+
+/// Largest payload a block can have, every symbol with the longest
+/// codeword.
+const MAX_BLOCK_BYTES: usize = MAX_BLOCK_SYMBOLS * MAX_CODE_LENGTH as usize / 8 + 1;
+
+fn pipeline() -> Pipeline {
+    Pipeline::new().then(Bwt).then(MoveToFront).then(ZeroRunLength)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SortBlockHeader {
+    /// Code lengths of the transformed symbols, as in `code_lengths_to_u32`
+    code_lengths: Vec<(u32, u8)>,
+    symbol_count: usize,
+    bit_count: usize
+}
+
+/// Transforms each block and codes it with a code of its own.
+pub struct BlockSortEncoder {
+    options: HuffmanOptions
+}
+
+impl BlockSortEncoder {
+    pub fn new(options: HuffmanOptions) -> Self {
+        BlockSortEncoder { options }
+    }
+}
+
+impl BlockCoder<u8> for BlockSortEncoder {
+    type Header = SortBlockHeader;
+
+    const BLOCK_LEN: usize = SORT_BLOCK_BYTES;
+
+    fn code_block(&self, data: &[u8]) -> io::Result<(SortBlockHeader, Vec<u8>)> {
+        let symbols = pipeline().forward(data.iter().map(|b| *b as u16).collect())?;
+
+        let mut frequencies = Frequencies::new();
+        for c in &symbols {
+            *frequencies.entry(*c).or_insert(0) += 1;
+        }
+        let code_lengths = code_lengths_for(&frequencies, self.options);
+        let codewords = canonical_codewords(&code_lengths);
+
+        let mut bits = BitWriter::new();
+        for c in &symbols {
+            let (code, len) = codewords[c];
+            bits.write(code, len as u32);
+        }
+        let (bytes, bit_count) = bits.finish();
+
+        let header = SortBlockHeader {
+            code_lengths: code_lengths_to_u32(&code_lengths),
+            symbol_count: symbols.len(),
+            bit_count
+        };
+        Ok((header, bytes))
+    }
+}
+
+pub struct BlockSortDecoder;
+
+impl SymbolDecoder for BlockSortDecoder {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let Some(header): Option<SortBlockHeader> = read_frame(&mut *reader)? else {
+            return Ok(false)
+        };
+
+        let len = header.bit_count.div_ceil(8);
+        if len > MAX_BLOCK_BYTES {
+            return Err(invalid_data(format!("Block of {len} bytes is too long")))
+        }
+        if header.symbol_count > MAX_BLOCK_SYMBOLS {
+            return Err(invalid_data(format!("Block of {} symbols is too long", header.symbol_count)))
+        }
+
+        let code_lengths: CodeLengths<u16> = code_lengths_from_u32(&header.code_lengths)
+            .ok_or_else(|| invalid_data("Invalid code lengths"))?;
+        let table = DecodeTable::new(&code_lengths)?;
+
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+        let mut bits = BitReader::new(&bytes);
+
+        let mut symbols = Vec::with_capacity(header.symbol_count);
+        for _ in 0..header.symbol_count {
+            let c = table.decode(&mut bits)
+                .ok_or_else(|| invalid_data("Invalid codeword in compressed stream"))?;
+            symbols.push(c);
+        }
+        if bits.position() != header.bit_count {
+            return Err(invalid_data("Block does not decode to its length"))
+        }
+
+        let block = pipeline().inverse(symbols, MAX_BLOCK_SYMBOLS)?;
+        if block.len() > SORT_BLOCK_BYTES {
+            return Err(invalid_data("Block decodes to too many bytes"))
+        }
+        for c in block {
+            let b = u8::try_from(c).map_err(|_| invalid_data(format!("Invalid symbol {c}")))?;
+            out.push(b);
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,240 @@
+//! Entropy coders the payload of a stream can be coded with, and the
+//! registry of those this build knows.
+//!
+//! Every coder has an identifier of its own, recorded in the header of
+//! the streams it codes (see `container`). Adding a coder is a matter of
+//! implementing `EntropyCoder` and listing it in `CODERS`.
+
+use std::fmt::Debug;
+
+use crate::adaptive::{AdaptiveDecoder, AdaptiveEncoder};
+use crate::block::{BlockDecoder, BlockEncoder, BufferedEncoder};
+use crate::block_sort::{BlockSortDecoder, BlockSortEncoder};
+use crate::huffman::{CodingMethod, HuffmanOptions, Symbol, SymbolMode};
+use crate::lz77::{LzDecoder, LzEncoder};
+use crate::range_coder::{RangeDecoder, RangeEncoder};
+use crate::stream::{SymbolDecoder, SymbolEncoder};
+use crate::tans::{TansDecoder, TansEncoder};
+
+/// Codes symbols into a payload and back. Stored payloads are left to the
+/// stream, any coder can fall back to them.
+pub trait EntropyCoder: Debug + Send + Sync {
+    /// Identifies the coder in the header, never to be given to another
+    fn id(&self) -> u8;
+
+    /// Name to show users
+    fn name(&self) -> &'static str;
+
+    /// The coding methods it implements, other than `Stored`.
+    fn methods(&self) -> &'static [CodingMethod];
+
+    /// Whether it codes with the Huffman code lengths of the input, which
+    /// `HuffmanOptions::max_code_length` limits.
+    fn uses_code_lengths(&self) -> bool {
+        false
+    }
+
+    /// Codes text one character at a time, or `None` when the coder only
+    /// codes bytes and text is to be coded as its UTF-8 bytes.
+    fn text_encoder(&self, _options: HuffmanOptions) -> Option<Box<dyn SymbolEncoder<char>>> {
+        None
+    }
+
+    fn byte_encoder(&self, options: HuffmanOptions) -> Box<dyn SymbolEncoder<u8>>;
+
+    /// Decodes what the encoder for `mode` and `method` wrote, or `None` if
+    /// the coder has no such encoder.
+    fn decoder(&self, mode: SymbolMode, method: CodingMethod) -> Option<Box<dyn SymbolDecoder>>;
+}
+
+/// Canonical Huffman codes, either one per block or adapting after every
+/// symbol.
+#[derive(Debug)]
+pub struct HuffmanCoder;
+
+impl HuffmanCoder {
+    fn encoder<S: Symbol>(options: HuffmanOptions) -> Box<dyn SymbolEncoder<S>> {
+        match options.method {
+            CodingMethod::Adaptive => Box::new(AdaptiveEncoder::new()),
+            _ => Box::new(BlockEncoder::new(options))
+        }
+    }
+
+    fn decoder<S: Symbol>(method: CodingMethod) -> Option<Box<dyn SymbolDecoder>> {
+        match method {
+            CodingMethod::Static => Some(Box::new(BlockDecoder::<S>::new(0))),
+            CodingMethod::Adaptive => Some(Box::new(AdaptiveDecoder::<S>::new())),
+            CodingMethod::Stored => None
+        }
+    }
+}
+
+impl EntropyCoder for HuffmanCoder {
+    fn id(&self) -> u8 {
+        0
+    }
+
+    fn name(&self) -> &'static str {
+        "Huffman"
+    }
+
+    fn methods(&self) -> &'static [CodingMethod] {
+        &[CodingMethod::Static, CodingMethod::Adaptive]
+    }
+
+    fn uses_code_lengths(&self) -> bool {
+        true
+    }
+
+    fn text_encoder(&self, options: HuffmanOptions) -> Option<Box<dyn SymbolEncoder<char>>> {
+        Some(Self::encoder(options))
+    }
+
+    fn byte_encoder(&self, options: HuffmanOptions) -> Box<dyn SymbolEncoder<u8>> {
+        Self::encoder(options)
+    }
+
+    fn decoder(&self, mode: SymbolMode, method: CodingMethod) -> Option<Box<dyn SymbolDecoder>> {
+        match mode {
+            SymbolMode::Text => Self::decoder::<char>(method),
+            SymbolMode::Binary => Self::decoder::<u8>(method)
+        }
+    }
+}
+
+/// Static range coding of blocks, see `range_coder`.
+#[derive(Debug)]
+pub struct RangeCoder;
+
+impl EntropyCoder for RangeCoder {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn name(&self) -> &'static str {
+        "Range coder"
+    }
+
+    fn methods(&self) -> &'static [CodingMethod] {
+        &[CodingMethod::Static]
+    }
+
+    fn text_encoder(&self, _options: HuffmanOptions) -> Option<Box<dyn SymbolEncoder<char>>> {
+        Some(Box::new(BufferedEncoder::new(RangeEncoder)))
+    }
+
+    fn byte_encoder(&self, _options: HuffmanOptions) -> Box<dyn SymbolEncoder<u8>> {
+        Box::new(BufferedEncoder::new(RangeEncoder))
+    }
+
+    fn decoder(&self, mode: SymbolMode, method: CodingMethod) -> Option<Box<dyn SymbolDecoder>> {
+        match (mode, method) {
+            (SymbolMode::Text, CodingMethod::Static) => Some(Box::new(RangeDecoder::<char>::new())),
+            (SymbolMode::Binary, CodingMethod::Static) => Some(Box::new(RangeDecoder::<u8>::new())),
+            _ => None
+        }
+    }
+}
+
+/// Table-based asymmetric numeral systems over blocks, see `tans`.
+#[derive(Debug)]
+pub struct TansCoder;
+
+impl EntropyCoder for TansCoder {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn name(&self) -> &'static str {
+        "tANS"
+    }
+
+    fn methods(&self) -> &'static [CodingMethod] {
+        &[CodingMethod::Static]
+    }
+
+    fn text_encoder(&self, _options: HuffmanOptions) -> Option<Box<dyn SymbolEncoder<char>>> {
+        Some(Box::new(BufferedEncoder::new(TansEncoder)))
+    }
+
+    fn byte_encoder(&self, _options: HuffmanOptions) -> Box<dyn SymbolEncoder<u8>> {
+        Box::new(BufferedEncoder::new(TansEncoder))
+    }
+
+    fn decoder(&self, mode: SymbolMode, method: CodingMethod) -> Option<Box<dyn SymbolDecoder>> {
+        match (mode, method) {
+            (SymbolMode::Text, CodingMethod::Static) => Some(Box::new(TansDecoder::<char>::new())),
+            (SymbolMode::Binary, CodingMethod::Static) => Some(Box::new(TansDecoder::<u8>::new())),
+            _ => None
+        }
+    }
+}
+
+/// Matches found by LZ77, Huffman coded as in DEFLATE, see `lz77`.
+#[derive(Debug)]
+pub struct Lz77Coder;
+
+impl EntropyCoder for Lz77Coder {
+    fn id(&self) -> u8 {
+        3
+    }
+
+    fn name(&self) -> &'static str {
+        "LZ77 + Huffman"
+    }
+
+    fn methods(&self) -> &'static [CodingMethod] {
+        &[CodingMethod::Static]
+    }
+
+    fn byte_encoder(&self, options: HuffmanOptions) -> Box<dyn SymbolEncoder<u8>> {
+        Box::new(BufferedEncoder::new(LzEncoder::new(options)))
+    }
+
+    fn decoder(&self, mode: SymbolMode, method: CodingMethod) -> Option<Box<dyn SymbolDecoder>> {
+        match (mode, method) {
+            (SymbolMode::Binary, CodingMethod::Static) => Some(Box::new(LzDecoder)),
+            _ => None
+        }
+    }
+}
+
+/// The Burrows-Wheeler transform, move-to-front and zero runs, then
+/// Huffman coding, as in bzip2, see `block_sort`.
+#[derive(Debug)]
+pub struct BlockSortCoder;
+
+impl EntropyCoder for BlockSortCoder {
+    fn id(&self) -> u8 {
+        4
+    }
+
+    fn name(&self) -> &'static str {
+        "BWT + MTF + Huffman"
+    }
+
+    fn methods(&self) -> &'static [CodingMethod] {
+        &[CodingMethod::Static]
+    }
+
+    fn byte_encoder(&self, options: HuffmanOptions) -> Box<dyn SymbolEncoder<u8>> {
+        Box::new(BufferedEncoder::new(BlockSortEncoder::new(options)))
+    }
+
+    fn decoder(&self, mode: SymbolMode, method: CodingMethod) -> Option<Box<dyn SymbolDecoder>> {
+        match (mode, method) {
+            (SymbolMode::Binary, CodingMethod::Static) => Some(Box::new(BlockSortDecoder)),
+            _ => None
+        }
+    }
+}
+
+/// Every coder this build can compress and decompress with, the default
+/// first.
+pub static CODERS: &[&dyn EntropyCoder] = &[&HuffmanCoder, &RangeCoder, &TansCoder, &Lz77Coder,
+                                              &BlockSortCoder];
+
+/// The coder recorded in a header as `id`.
+pub fn coder(id: u8) -> Option<&'static dyn EntropyCoder> {
+    CODERS.iter().copied().find(|coder| coder.id() == id)
+}
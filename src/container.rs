@@ -0,0 +1,231 @@
+//! The header every compressed file starts with.
+//!
+//! | Bytes | Contents                                                    |
+//! |-------|-------------------------------------------------------------|
+//! | 4     | Magic number, `HUF` followed by `0x1A`                      |
+//! | 1     | Format version, currently 3                                 |
+//! | 1     | Coder of the payload, as its identifier (see `codec`)       |
+//! | 2     | Flags, little endian, see below                             |
+//! | 8     | Original length in bytes, little endian, if flag 3 is set   |
+//! | 2 + n | Original file name, as its length in bytes (little endian)  |
+//! |       | and then its UTF-8 bytes, if flag 4 is set                  |
+//! | 8     | Modification time in seconds since the Unix epoch, little   |
+//! |       | endian and signed, if flag 5 is set                         |
+//! | 4     | Unix permission bits, little endian, if flag 6 is set       |
+//!
+//! Flag bits, from the least significant:
+//!
+//! - 0: clear when the input was coded as UTF-8 text, set when as bytes
+//! - 1 and 2: the coding method, 0 for static, 1 for adaptive and 2 for
+//!   stored, whatever the coder
+//! - 3 to 6: the optional field above that names the bit follows. Stored
+//!   payloads leave the length out, their framing already gives it
+//! - 7: set when runs of equal symbols were shortened before coding (see
+//!   `run_length`)
+//!
+//! The other bits are clear, files with any of them set are rejected.
+//!
+//! The payload comes right after the header, in the layout of its coder
+//! and coding method (see `codec` and `stored`), and the file ends with the
+//! CRC-32 of the original contents, 4 bytes little endian.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::codec::{self, EntropyCoder};
+use crate::error::HuffmanError;
+use crate::huffman::{CodingMethod, SymbolMode};
+use crate::stream::invalid_data;
+
+pub const MAGIC: [u8; 4] = *b"HUF\x1a";
+
+/// Version of the format written, the only one read back.
+pub const FORMAT_VERSION: u8 = 3;
+
+const BINARY_FLAG: u16 = 1 << 0;
+const METHOD_SHIFT: u32 = 1;
+const METHOD_MASK: u16 = 0b11 << METHOD_SHIFT;
+const LENGTH_FLAG: u16 = 1 << 3;
+const NAME_FLAG: u16 = 1 << 4;
+const MODIFIED_FLAG: u16 = 1 << 5;
+const PERMISSIONS_FLAG: u16 = 1 << 6;
+const RUN_LENGTH_FLAG: u16 = 1 << 7;
+
+const KNOWN_FLAGS: u16 = BINARY_FLAG | METHOD_MASK | LENGTH_FLAG | NAME_FLAG | MODIFIED_FLAG
+    | PERMISSIONS_FLAG | RUN_LENGTH_FLAG;
+
+/// What the header records about the original file, all of it optional.
+#[derive(Clone, Default, Debug)]
+pub struct FileInfo {
+    /// Length of the original contents, when known before compressing
+    pub len: Option<u64>,
+    pub name: Option<String>,
+    /// Seconds since the Unix epoch
+    pub modified: Option<i64>,
+    /// Unix permission bits
+    pub permissions: Option<u32>
+}
+
+impl FileInfo {
+    /// Everything about the file at `path` that can be recorded, given its
+    /// metadata.
+    pub fn of_file(path: &Path, metadata: &fs::Metadata) -> Self {
+        let modified = metadata.modified().ok().map(|time| match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64)
+        });
+
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let permissions = None;
+
+        FileInfo {
+            len: Some(metadata.len()),
+            name: path.file_name().and_then(|name| name.to_str()).map(String::from),
+            modified,
+            permissions
+        }
+    }
+
+    /// The recorded name, if it is a plain file name that cannot lead
+    /// outside the directory it is extracted to.
+    pub fn file_name(&self) -> Option<&str> {
+        self.name.as_deref()
+            .filter(|name| Path::new(name).file_name().is_some_and(|file_name| file_name == *name))
+    }
+
+    /// Gives `file` the recorded modification time and permissions.
+    pub fn apply_to(&self, file: &File) -> io::Result<()> {
+        if let Some(modified) = self.modified {
+            let offset = Duration::from_secs(modified.unsigned_abs());
+            let time = if modified >= 0 { UNIX_EPOCH + offset } else { UNIX_EPOCH - offset };
+            file.set_modified(time)?;
+        }
+
+        #[cfg(unix)]
+        if let Some(permissions) = self.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(permissions))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct StreamHeader {
+    pub coder: &'static dyn EntropyCoder,
+    pub mode: SymbolMode,
+    pub method: CodingMethod,
+    /// Whether runs were shortened before coding
+    pub run_length: bool,
+    pub info: FileInfo
+}
+
+fn read_array<const N: usize>(mut reader: impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl StreamHeader {
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let FileInfo { len, name, modified, permissions } = &self.info;
+        let len = &len.filter(|_| self.method != CodingMethod::Stored);
+
+        let mut flags = match self.method {
+            CodingMethod::Static => 0,
+            CodingMethod::Adaptive => 1,
+            CodingMethod::Stored => 2
+        } << METHOD_SHIFT;
+        for (flag, present) in [(BINARY_FLAG, self.mode == SymbolMode::Binary),
+                                (LENGTH_FLAG, len.is_some()),
+                                (NAME_FLAG, name.is_some()),
+                                (MODIFIED_FLAG, modified.is_some()),
+                                (PERMISSIONS_FLAG, permissions.is_some()),
+                                (RUN_LENGTH_FLAG, self.run_length)] {
+            if present {
+                flags |= flag;
+            }
+        }
+
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION, self.coder.id()])?;
+        writer.write_all(&flags.to_le_bytes())?;
+        if let Some(len) = len {
+            writer.write_all(&len.to_le_bytes())?;
+        }
+        if let Some(name) = name {
+            let name_len = u16::try_from(name.len())
+                .map_err(|_| invalid_data("File name is too long to record"))?;
+            writer.write_all(&name_len.to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+        }
+        if let Some(modified) = modified {
+            writer.write_all(&modified.to_le_bytes())?;
+        }
+        if let Some(permissions) = permissions {
+            writer.write_all(&permissions.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(mut reader: impl Read) -> io::Result<Self> {
+        if read_array(&mut reader)? != MAGIC {
+            return Err(invalid_data("Not a compressed file (wrong magic number)"))
+        }
+
+        let [version] = read_array(&mut reader)?;
+        if version != FORMAT_VERSION {
+            return Err(HuffmanError::UnsupportedVersion { found: version, supported: FORMAT_VERSION }.into_io())
+        }
+
+        let [coder_id] = read_array(&mut reader)?;
+        let coder = codec::coder(coder_id)
+            .ok_or_else(|| invalid_data(format!("Unknown coder {coder_id}")))?;
+
+        let flags = u16::from_le_bytes(read_array(&mut reader)?);
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(invalid_data(format!("Unknown flags {:#06x}", flags & !KNOWN_FLAGS)))
+        }
+
+        let mode = if flags & BINARY_FLAG != 0 { SymbolMode::Binary } else { SymbolMode::Text };
+        let method = match (flags & METHOD_MASK) >> METHOD_SHIFT {
+            0 => CodingMethod::Static,
+            1 => CodingMethod::Adaptive,
+            2 => CodingMethod::Stored,
+            n => return Err(invalid_data(format!("Unknown coding method {n}")))
+        };
+
+        let mut info = FileInfo::default();
+        if flags & LENGTH_FLAG != 0 {
+            info.len = Some(u64::from_le_bytes(read_array(&mut reader)?));
+        }
+        if flags & NAME_FLAG != 0 {
+            let name_len = u16::from_le_bytes(read_array(&mut reader)?);
+            let mut name = vec![0; name_len as usize];
+            reader.read_exact(&mut name)?;
+            info.name = Some(String::from_utf8(name)
+                .map_err(|_| invalid_data("File name is not valid UTF-8"))?);
+        }
+        if flags & MODIFIED_FLAG != 0 {
+            info.modified = Some(i64::from_le_bytes(read_array(&mut reader)?));
+        }
+        if flags & PERMISSIONS_FLAG != 0 {
+            info.permissions = Some(u32::from_le_bytes(read_array(&mut reader)?));
+        }
+
+        let run_length = flags & RUN_LENGTH_FLAG != 0;
+        Ok(StreamHeader { coder, mode, method, run_length, info })
+    }
+}
+
+/// Whether `reader` starts like a compressed file.
+pub fn has_magic(reader: impl Read) -> bool {
+    read_array(reader).is_ok_and(|magic| magic == MAGIC)
+}
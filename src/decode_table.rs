@@ -0,0 +1,153 @@
+use std::io;
+
+use crate::bits::BitReader;
+use crate::huffman::{canonical_codes, Symbol, MAX_CODE_LENGTH};
+use crate::stream::invalid_data;
+
+/// Number of bits looked up at once. Codewords up to this length are
+/// decoded with a single table access.
+const LOOKUP_BITS: u32 = 10;
+
+/// Decodes canonical codewords several bits at a time.
+///
+/// Every possible `LOOKUP_BITS` wide bit pattern indexes an entry holding
+/// the symbol whose codeword is a prefix of it. Longer codewords miss the
+/// table and are found by comparing against the first canonical code of
+/// each length instead.
+pub struct DecodeTable<S> {
+    lookup_bits: u32,
+    lookup: Vec<Option<(S, u8)>>,
+    max_len: u32,
+    // Indexed by code length
+    first_code: Vec<u64>,
+    count: Vec<u64>,
+    first_index: Vec<usize>,
+    // Symbols in canonical order
+    sorted: Vec<S>
+}
+
+/// Whether codewords of `code_lengths` can all be told apart, by the
+/// Kraft inequality: each length `len` takes up `2^-len` of the codes.
+fn fits_kraft<S>(code_lengths: &[(S, u8)]) -> bool {
+    let mut used: u64 = 0;
+    for (_, len) in code_lengths {
+        if *len == 0 || *len > MAX_CODE_LENGTH {
+            return false
+        }
+        used += 1 << (MAX_CODE_LENGTH - len);
+        if used > 1 << MAX_CODE_LENGTH {
+            return false
+        }
+    }
+    true
+}
+
+impl<S: Symbol> DecodeTable<S> {
+    /// A table for `code_lengths`, which come from the compressed stream
+    /// and are rejected when over-subscribed.
+    pub fn new(code_lengths: &[(S, u8)]) -> io::Result<Self> {
+        if !fits_kraft(code_lengths) {
+            return Err(invalid_data("Over-subscribed code lengths"))
+        }
+
+        let codes = canonical_codes(code_lengths);
+        let max_len = codes.iter().map(|(_, _, len)| *len as u32).max().unwrap_or(0);
+        let lookup_bits = LOOKUP_BITS.min(max_len);
+
+        let mut lookup = vec![None; 1 << lookup_bits];
+        let mut first_code = vec![0; max_len as usize + 1];
+        let mut count = vec![0; max_len as usize + 1];
+        let mut first_index = vec![0; max_len as usize + 1];
+        let mut sorted = Vec::with_capacity(codes.len());
+
+        for (i, (c, code, len)) in codes.iter().enumerate() {
+            let len_idx = *len as usize;
+            if count[len_idx] == 0 {
+                first_code[len_idx] = *code;
+                first_index[len_idx] = i;
+            }
+            count[len_idx] += 1;
+            sorted.push(*c);
+
+            if (*len as u32) <= lookup_bits {
+                let spare_bits = lookup_bits - *len as u32;
+                let start = (*code << spare_bits) as usize;
+                let end = ((*code + 1) << spare_bits) as usize;
+                lookup[start..end].fill(Some((*c, *len)));
+            }
+        }
+
+        Ok(DecodeTable {
+            lookup_bits,
+            lookup,
+            max_len,
+            first_code,
+            count,
+            first_index,
+            sorted
+        })
+    }
+
+    /// Reads the next symbol, or `None` if the bits are not a codeword.
+    pub fn decode(&self, reader: &mut BitReader) -> Option<S> {
+        let bits = reader.peek(self.lookup_bits);
+        if let Some((c, len)) = self.lookup[bits as usize] {
+            reader.consume(len as u32);
+            return Some(c)
+        }
+
+        for len in (self.lookup_bits + 1)..=self.max_len {
+            let code = reader.peek(len);
+            let offset = code.wrapping_sub(self.first_code[len as usize]);
+            if offset < self.count[len as usize] {
+                reader.consume(len);
+                return Some(self.sorted[self.first_index[len as usize] + offset as usize])
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+    use crate::huffman::canonical_codewords;
+
+    #[test]
+    fn over_subscribed_lengths_are_rejected() {
+        // Three codewords of one bit, as a corrupted header could hold
+        let lengths = [(b'a', 1), (b'b', 1), (b'c', 1)];
+        let err = DecodeTable::new(&lengths).err().expect("Over-subscribed lengths to be rejected");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Too many short codewords among long ones, past the lookup table
+        let mut lengths: Vec<(u16, u8)> = (0..1020).map(|c| (c, 10)).collect();
+        lengths.extend((1020..1030).map(|c| (c, 12)));
+        lengths.push((2000, 2));
+        assert!(DecodeTable::new(&lengths).is_err());
+    }
+
+    #[test]
+    fn complete_and_incomplete_codes_decode() {
+        let lengths = [(b'a', 1), (b'b', 2), (b'c', 3), (b'd', 3)];
+        let table = DecodeTable::new(&lengths).unwrap();
+        let codewords = canonical_codewords(&lengths);
+
+        let text = b"abacabad";
+        let mut writer = BitWriter::new();
+        for c in text {
+            let (code, len) = codewords[c];
+            writer.write(code, len as u32);
+        }
+        let (bytes, _) = writer.finish();
+
+        let mut reader = BitReader::new(&bytes);
+        let decoded: Vec<u8> = text.iter().map(|_| table.decode(&mut reader).unwrap()).collect();
+        assert_eq!(decoded, text);
+
+        // A lone symbol has a one bit codeword, leaving half the codes unused
+        assert!(DecodeTable::new(&[(b'z', 1)]).is_ok());
+    }
+}
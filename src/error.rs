@@ -0,0 +1,70 @@
+//! The error every public function compressing or decompressing returns.
+//!
+//! The stream layer works with `io::Error`, as `Read` and `Write` demand.
+//! Errors it raises that are worth telling apart carry a `HuffmanError`
+//! inside, which the conversion from `io::Error` takes back out.
+
+use std::{error::Error, fmt, io};
+
+use crate::stream::ChecksumMismatch;
+
+#[derive(Debug)]
+pub enum HuffmanError {
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// The input is not a well formed compressed file or archive
+    Format(String),
+    /// The file decoded, but not into what was compressed
+    Corrupted(ChecksumMismatch),
+    /// The file was written by a newer version of the format
+    UnsupportedVersion { found: u8, supported: u8 },
+    /// There is nothing to compress
+    EmptyInput
+}
+
+impl HuffmanError {
+    /// Wraps the error for the stream layer, so that it survives the trip
+    /// through `io::Error`.
+    pub fn into_io(self) -> io::Error {
+        match self {
+            HuffmanError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err)
+        }
+    }
+}
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HuffmanError::Io(err) => write!(f, "{err}"),
+            HuffmanError::Format(msg) => f.write_str(msg),
+            HuffmanError::Corrupted(mismatch) => write!(f, "{mismatch}"),
+            HuffmanError::UnsupportedVersion { found, supported } =>
+                write!(f, "Unsupported format version {found}, only version {supported} can be read"),
+            HuffmanError::EmptyInput => f.write_str("No content to compress")
+        }
+    }
+}
+
+impl Error for HuffmanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HuffmanError::Io(err) => Some(err),
+            HuffmanError::Corrupted(mismatch) => Some(mismatch),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for HuffmanError {
+    fn from(err: io::Error) -> Self {
+        match err.downcast::<HuffmanError>() {
+            Ok(err) => err,
+            Err(err) => match err.kind() {
+                io::ErrorKind::InvalidData => HuffmanError::Format(err.to_string()),
+                io::ErrorKind::UnexpectedEof => HuffmanError::Format("The file ends too early".to_owned()),
+                _ => HuffmanError::Io(err)
+            }
+        }
+    }
+}
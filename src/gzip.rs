@@ -0,0 +1,350 @@
+//! Standard gzip output, for tools that read gzip rather than our own
+//! format: a single gzip member (RFC 1952) holding DEFLATE blocks (RFC
+//! 1951), which `gzip -d` and `zcat` decompress.
+//!
+//! Every block is cut into tokens as by the LZ77 coder (see `lz77`), and
+//! coded with dynamic Huffman codes built by `code_lengths_for`, limited
+//! to the 15 bits DEFLATE allows. Should a block come out larger than its
+//! bytes, it is stored as is instead.
+
+use std::io::{self, Write};
+
+use crate::bits::LsbBitWriter;
+use crate::container::FileInfo;
+use crate::crc32::Crc32;
+use crate::huffman::{canonical_codes, code_lengths_for, CodeLengths, Frequencies, HuffmanOptions, Symbol};
+use crate::lz77::{code_of, token_frequencies, tokens, Token, DISTANCE_BASES, DISTANCE_EXTRA_BITS, FIRST_LENGTH_SYMBOL,
+                  LENGTH_BASES, LENGTH_EXTRA_BITS, LZ_BLOCK_BYTES};
+
+pub const GZIP_FILE_EXTENSION: &str = "gz";
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE_METHOD: u8 = 8;
+const NAME_FLAG: u8 = 1 << 3;
+const UNKNOWN_OS: u8 = 255;
+
+/// Longest codeword DEFLATE allows.
+const MAX_DEFLATE_CODE_LENGTH: u8 = 15;
+
+/// Longest codeword of the code the code lengths are coded with.
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+
+const END_OF_BLOCK: u16 = 256;
+const LITERAL_SYMBOLS: usize = 286;
+const DISTANCE_SYMBOLS: usize = 30;
+const CODE_LENGTH_SYMBOLS: usize = 19;
+
+/// Order the lengths of the code length code are written in.
+const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_SYMBOLS] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Code length symbols for runs: the previous length 3 to 6 times, then
+/// 3 to 10 zeros, then 11 to 138 zeros.
+const REPEAT_PREVIOUS: u8 = 16;
+const SHORT_ZEROS: u8 = 17;
+const LONG_ZEROS: u8 = 18;
+
+const STORED_BLOCK_BYTES: usize = u16::MAX as usize;
+
+const BLOCK_TYPE_STORED: u64 = 0;
+const BLOCK_TYPE_DYNAMIC: u64 = 2;
+
+/// Extra bits that follow a code length symbol.
+fn code_length_extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        REPEAT_PREVIOUS => 2,
+        SHORT_ZEROS => 3,
+        LONG_ZEROS => 7,
+        _ => 0
+    }
+}
+
+/// Code lengths of the symbols `0..n`, 0 for those without a codeword.
+fn length_table<S: Symbol>(code_lengths: &CodeLengths<S>, n: usize) -> Vec<u8> {
+    let mut lengths = vec![0; n];
+    for (c, len) in code_lengths {
+        lengths[c.to_u32() as usize] = *len;
+    }
+    lengths
+}
+
+/// Canonical codewords of the symbols `0..lengths.len()`, as DEFLATE
+/// assigns them: by length, then by symbol.
+fn code_table(lengths: &[u8]) -> Vec<(u64, u8)> {
+    let code_lengths: CodeLengths<u16> = lengths.iter().enumerate()
+        .filter(|(_, len)| **len > 0)
+        .map(|(c, len)| (c as u16, *len))
+        .collect();
+
+    let mut codes = vec![(0, 0); lengths.len()];
+    for (c, code, len) in canonical_codes(&code_lengths) {
+        codes[c as usize] = (code, len);
+    }
+    codes
+}
+
+/// `lengths` as code length symbols and the values of their extra bits.
+fn run_length_code(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut coded = Vec::new();
+    let mut previous = None;
+
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|l| **l == len).count();
+
+        if len == 0 && run >= 3 {
+            let n = run.min(138);
+            coded.push(if n >= 11 { (LONG_ZEROS, (n - 11) as u8) } else { (SHORT_ZEROS, (n - 3) as u8) });
+            i += n;
+        } else if previous == Some(len) && run >= 3 {
+            let n = run.min(6);
+            coded.push((REPEAT_PREVIOUS, (n - 3) as u8));
+            i += n;
+        } else {
+            coded.push((len, 0));
+            i += 1;
+        }
+        previous = Some(len);
+    }
+
+    coded
+}
+
+/// Number of symbols worth sending out of `lengths`, at least `min`.
+fn sent_len(lengths: &[u8], min: usize) -> usize {
+    lengths.iter().rposition(|len| *len > 0).map_or(0, |i| i + 1).max(min)
+}
+
+/// A block with dynamic codes, worked out before writing it to know its
+/// size.
+struct DynamicBlock {
+    tokens: Vec<Token>,
+    literal_codes: Vec<(u64, u8)>,
+    distance_codes: Vec<(u64, u8)>,
+    literal_count: usize,
+    distance_count: usize,
+    code_length_lengths: [u8; CODE_LENGTH_SYMBOLS],
+    code_length_count: usize,
+    coded_lengths: Vec<(u8, u8)>,
+    bit_len: usize
+}
+
+impl DynamicBlock {
+    fn new(data: &[u8], options: HuffmanOptions) -> Self {
+        let tokens = tokens(data);
+
+        let (mut literal_frequencies, mut distance_frequencies, extra_bits) = token_frequencies(&tokens);
+        literal_frequencies.insert(END_OF_BLOCK, 1);
+        // Inflaters expect a distance code even in blocks without matches
+        if distance_frequencies.is_empty() {
+            distance_frequencies.insert(0, 1);
+        }
+
+        let max_code_length = options.max_code_length.unwrap_or(MAX_DEFLATE_CODE_LENGTH).min(MAX_DEFLATE_CODE_LENGTH);
+        let options = HuffmanOptions { max_code_length: Some(max_code_length), ..options };
+        let literal_lengths = length_table(&code_lengths_for(&literal_frequencies, options), LITERAL_SYMBOLS);
+        let distance_lengths = length_table(&code_lengths_for(&distance_frequencies, options), DISTANCE_SYMBOLS);
+
+        let literal_count = sent_len(&literal_lengths, END_OF_BLOCK as usize + 1);
+        let distance_count = sent_len(&distance_lengths, 1);
+        let coded_lengths = run_length_code(&[&literal_lengths[..literal_count],
+                                              &distance_lengths[..distance_count]].concat());
+
+        let mut code_length_frequencies = Frequencies::new();
+        for (symbol, _) in &coded_lengths {
+            *code_length_frequencies.entry(*symbol).or_insert(0) += 1;
+        }
+        // A lone symbol would get an incomplete code, which inflaters
+        // reject for code lengths
+        if code_length_frequencies.len() == 1 {
+            let unused = if code_length_frequencies.contains_key(&0) { 1 } else { 0 };
+            code_length_frequencies.insert(unused, 1);
+        }
+        let options = HuffmanOptions { max_code_length: Some(MAX_CODE_LENGTH_CODE_LENGTH), ..options };
+        let code_length_lengths: [u8; CODE_LENGTH_SYMBOLS] =
+            length_table(&code_lengths_for(&code_length_frequencies, options), CODE_LENGTH_SYMBOLS)
+                .try_into().expect("A length for every code length symbol");
+        let code_length_count = CODE_LENGTH_ORDER.iter()
+            .rposition(|c| code_length_lengths[*c] > 0)
+            .map_or(0, |i| i + 1)
+            .max(4);
+
+        let bit_len = 3 + 5 + 5 + 4 + 3 * code_length_count
+            + coded_lengths.iter()
+                .map(|(symbol, _)| code_length_lengths[*symbol as usize] as usize
+                     + code_length_extra_bits(*symbol) as usize)
+                .sum::<usize>()
+            + literal_frequencies.iter().map(|(c, n)| n * literal_lengths[*c as usize] as usize).sum::<usize>()
+            + distance_frequencies.iter().map(|(c, n)| n * distance_lengths[*c as usize] as usize).sum::<usize>()
+            + extra_bits;
+
+        DynamicBlock {
+            tokens,
+            literal_codes: code_table(&literal_lengths),
+            distance_codes: code_table(&distance_lengths),
+            literal_count,
+            distance_count,
+            code_length_lengths,
+            code_length_count,
+            coded_lengths,
+            bit_len
+        }
+    }
+
+    fn write_to(&self, bits: &mut LsbBitWriter, last: bool) {
+        bits.write(last as u64, 1);
+        bits.write(BLOCK_TYPE_DYNAMIC, 2);
+        bits.write((self.literal_count - 257) as u64, 5);
+        bits.write((self.distance_count - 1) as u64, 5);
+        bits.write((self.code_length_count - 4) as u64, 4);
+        for c in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            bits.write(self.code_length_lengths[*c] as u64, 3);
+        }
+
+        let code_length_codes = code_table(&self.code_length_lengths);
+        for (symbol, extra) in &self.coded_lengths {
+            let (code, len) = code_length_codes[*symbol as usize];
+            bits.write_code(code, len as u32);
+            bits.write(*extra as u64, code_length_extra_bits(*symbol));
+        }
+
+        for token in &self.tokens {
+            match *token {
+                Token::Literal(b) => {
+                    let (code, len) = self.literal_codes[b as usize];
+                    bits.write_code(code, len as u32);
+                },
+                Token::Match { length, distance } => {
+                    let (length_code, extra_bits, extra) = code_of(length, &LENGTH_BASES, &LENGTH_EXTRA_BITS);
+                    let (code, len) = self.literal_codes[FIRST_LENGTH_SYMBOL as usize + length_code];
+                    bits.write_code(code, len as u32);
+                    bits.write(extra as u64, extra_bits as u32);
+
+                    let (distance_code, extra_bits, extra) = code_of(distance, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS);
+                    let (code, len) = self.distance_codes[distance_code];
+                    bits.write_code(code, len as u32);
+                    bits.write(extra as u64, extra_bits as u32);
+                }
+            }
+        }
+
+        let (code, len) = self.literal_codes[END_OF_BLOCK as usize];
+        bits.write_code(code, len as u32);
+    }
+}
+
+/// Size of `len` bytes in stored blocks, counting the most padding.
+fn stored_bit_len(len: usize) -> usize {
+    let blocks = len.div_ceil(STORED_BLOCK_BYTES).max(1);
+    blocks * (3 + 7 + 32) + len * 8
+}
+
+fn write_stored(bits: &mut LsbBitWriter, data: &[u8], last: bool) {
+    let chunks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(STORED_BLOCK_BYTES).collect() };
+    let last_chunk = chunks.len() - 1;
+
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        bits.write((last && i == last_chunk) as u64, 1);
+        bits.write(BLOCK_TYPE_STORED, 2);
+        bits.align();
+        let len = chunk.len() as u16;
+        bits.write_bytes(&len.to_le_bytes());
+        bits.write_bytes(&(!len).to_le_bytes());
+        bits.write_bytes(chunk);
+    }
+}
+
+/// Compresses everything written to it into a gzip member in `W`, one
+/// block at a time. Call `finish` once all the input has been written.
+///
+/// Of the options, only `max_code_length` has a say, DEFLATE being a
+/// coder of its own.
+pub struct GzipEncoder<W: Write> {
+    writer: W,
+    bits: LsbBitWriter,
+    block: Vec<u8>, // Input of the block being gathered
+    options: HuffmanOptions,
+    written: u64,
+    crc: Crc32
+}
+
+impl<W: Write> GzipEncoder<W> {
+    /// Starts the member with a header recording the name and
+    /// modification time in `info`, if any.
+    pub fn new(mut writer: W, info: &FileInfo, options: HuffmanOptions) -> io::Result<Self> {
+        // The name ends at a zero byte, so one with a zero byte in it
+        // cannot be recorded
+        let name = info.name.as_ref().filter(|name| !name.contains('\0'));
+        let modified = info.modified.and_then(|modified| u32::try_from(modified).ok()).unwrap_or(0);
+
+        let flags = if name.is_some() { NAME_FLAG } else { 0 };
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[DEFLATE_METHOD, flags])?;
+        writer.write_all(&modified.to_le_bytes())?;
+        writer.write_all(&[0, UNKNOWN_OS])?;
+        if let Some(name) = name {
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&[0])?;
+        }
+
+        Ok(GzipEncoder {
+            writer,
+            bits: LsbBitWriter::new(),
+            block: Vec::with_capacity(LZ_BLOCK_BYTES),
+            options,
+            written: 0,
+            crc: Crc32::new()
+        })
+    }
+
+    fn write_block(&mut self, last: bool) -> io::Result<()> {
+        let block = DynamicBlock::new(&self.block, self.options);
+        if block.bit_len < stored_bit_len(self.block.len()) {
+            block.write_to(&mut self.bits, last);
+        } else {
+            write_stored(&mut self.bits, &self.block, last);
+        }
+
+        self.writer.write_all(&self.bits.take_bytes())?;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last block, the checksum and the length of the input.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block(true)?;
+        self.bits.align();
+        self.writer.write_all(&self.bits.take_bytes())?;
+
+        self.writer.write_all(&self.crc.value().to_le_bytes())?;
+        // Only the length modulo 2^32 is recorded
+        self.writer.write_all(&(self.written as u32).to_le_bytes())?;
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for GzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            // A full block is only written once there is more, as the last
+            // one is marked as such
+            if self.block.len() == LZ_BLOCK_BYTES {
+                self.write_block(false)?;
+            }
+            let n = (LZ_BLOCK_BYTES - self.block.len()).min(rest.len());
+            self.block.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+        }
+
+        self.written += buf.len() as u64;
+        self.crc.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -1,288 +1,662 @@
-
-use std::{collections::{BinaryHeap, HashMap}, io::{BufWriter, Write}};
+use std::{collections::{BTreeMap, BinaryHeap, HashMap}, fmt::Debug, hash::Hash};
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path};
 
 use serde::{Serialize, Deserialize};
-use postcard;
+
+use crate::block::{choose_table, BLOCK_SYMBOLS};
+use crate::codec::{EntropyCoder, HuffmanCoder};
+use crate::container::{self, FileInfo};
+use crate::error::HuffmanError;
+use crate::gzip::{GzipEncoder, GZIP_FILE_EXTENSION};
+use crate::package_merge;
+use crate::stream::{decode_utf8, max_compressed_len, HuffmanDecoder, HuffmanEncoder};
+
+/// Anything that can sit in the leaves of a Huffman tree: characters when
+/// compressing text and raw bytes when compressing arbitrary files.
+///
+/// Symbols are written to compressed files as plain integers (the Unicode
+/// scalar value of a `char`, the value of a byte), so that the format does
+/// not depend on how this program represents them.
+pub trait Symbol: Copy + Ord + Hash + Debug + Send + Sync + 'static {
+    fn to_u32(self) -> u32;
+    fn from_u32(n: u32) -> Option<Self>;
+
+    /// Bits needed to write any value `to_u32` returns.
+    const RAW_BITS: u32;
+
+    /// Appends the symbol to decompressed output.
+    fn push_to(self, bytes: &mut Vec<u8>);
+}
+
+impl Symbol for char {
+    const RAW_BITS: u32 = 21;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(n: u32) -> Option<Self> {
+        char::from_u32(n)
+    }
+
+    fn push_to(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(self.encode_utf8(&mut [0; 4]).as_bytes())
+    }
+}
+
+impl Symbol for u8 {
+    const RAW_BITS: u32 = 8;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(n: u32) -> Option<Self> {
+        u8::try_from(n).ok()
+    }
+
+    fn push_to(self, bytes: &mut Vec<u8>) {
+        bytes.push(self)
+    }
+}
+
+/// Symbols of alphabets wider than a byte, such as the lengths and
+/// distances of `lz77`. Never the contents of a file on their own, they
+/// are pushed as two bytes, little endian.
+impl Symbol for u16 {
+    const RAW_BITS: u32 = 16;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(n: u32) -> Option<Self> {
+        u16::try_from(n).ok()
+    }
+
+    fn push_to(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes())
+    }
+}
 
 #[derive(Eq, PartialEq, PartialOrd,
-         Clone, Debug,
-         Serialize, Deserialize)]
-enum HuffmanTree {
-    Leaf(char),
-    Node((Box<HuffmanTree>, Box<HuffmanTree>))
+         Clone, Debug)]
+enum HuffmanTree<S> {
+    Leaf(S),
+    Node((Box<HuffmanTree<S>>, Box<HuffmanTree<S>>))
+}
+
+/// Whether the original file was coded as UTF-8 text or as raw bytes.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SymbolMode {
+    Text,
+    Binary
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SerialisedHuffmanTree {
-    tree: HuffmanTree,
-    senses_count: usize,
-    encoded_chars: Vec<u8>
+/// How the symbols of a stream are given their codewords.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum CodingMethod {
+    /// Codes computed per block and stored in the stream
+    #[default]
+    Static,
+    /// A single pass with a code updated after every symbol, see `adaptive`
+    Adaptive,
+    /// No coding at all, for input that coding would make bigger
+    Stored
 }
 
-type Frequencies = [usize; 256];
+/// Number of occurrences of each symbol in the input. Only the symbols
+/// that actually appear are stored, so any `char` can be counted.
+pub type Frequencies<S> = BTreeMap<S, usize>;
+
+/// Length of the codeword of each symbol, in increasing symbol order.
+pub type CodeLengths<S> = Vec<(S, u8)>;
 
 #[derive(Eq, PartialEq, Debug)]
-pub struct HuffmanFreqTree {
-    frequencies: Frequencies,
-    tree: HuffmanTree
+pub struct HuffmanFreqTree<S> {
+    weight: usize,
+    tree: HuffmanTree<S>
+}
+
+/// Follows the code `BlockEncoder` gives each block of the symbols pushed
+/// to it, adding up the lengths of the codewords, without coding anything.
+struct CodeLengthTally<S> {
+    block: Frequencies<S>,
+    block_symbols: usize,
+    previous: Option<CodeLengths<S>>,
+    options: HuffmanOptions,
+    symbols: usize,
+    bits: usize,
+    max: u8,
+    unconstrained_bits: usize,
+    unconstrained_max: u8
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HuffmanOptions {
+    /// Coder of the payload, one of `codec::CODERS`
+    pub coder: &'static dyn EntropyCoder,
+    /// Longest codeword allowed, in bits. When set, the code lengths are
+    /// computed with package-merge instead of being read off the tree.
+    pub max_code_length: Option<u8>,
+    pub method: CodingMethod,
+    /// Threads coding blocks at once, 0 meaning one per core. The output
+    /// does not depend on it.
+    pub threads: usize,
+    /// Whether to record the file name, modification time and permissions
+    pub store_metadata: bool,
+    /// Whether to shorten runs of equal symbols before coding, for inputs
+    /// such as padding or zero-filled data. Stored streams ignore it.
+    pub run_length: bool
+}
+
+impl Default for HuffmanOptions {
+    fn default() -> Self {
+        HuffmanOptions {
+            coder: &HuffmanCoder,
+            max_code_length: None,
+            method: CodingMethod::default(),
+            threads: 0,
+            store_metadata: false,
+            run_length: false
+        }
+    }
 }
 
+/// Codeword lengths of the codes the blocks of a file are given, compared
+/// with those of a plain Huffman tree built for each block. Averages are
+/// in bits per symbol.
+#[derive(Clone, Copy, Debug)]
+pub struct CodeLengthReport {
+    pub average: f64,
+    pub max: u8,
+    pub unconstrained_average: f64,
+    pub unconstrained_max: u8
+}
+
+/// What the header of the compressed file at `filepath` records.
+fn file_info(filepath: &str, metadata: &std::fs::Metadata, options: HuffmanOptions) -> FileInfo {
+    if options.store_metadata {
+        FileInfo::of_file(Path::new(filepath), metadata)
+    } else {
+        FileInfo { len: Some(metadata.len()), ..FileInfo::default() }
+    }
+}
+
+/// Counts the bytes written to it, throwing them away.
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What compressing a file needs to know of it beforehand. Files that are
+/// valid UTF-8 are coded character by character, everything else byte by
+/// byte.
+///
+/// Only the code lengths are written to the compressed file: the codewords
+/// themselves are canonical and can be rebuilt from the lengths alone.
 #[derive(Debug)]
 pub struct Huffman {
-    freq_tree: HuffmanFreqTree,
-    text: String
+    mode: SymbolMode,
+    report: CodeLengthReport
 }
 
-#[derive(Eq, PartialEq, Hash, Debug, Clone)]
-enum Sense {
-    Left,
-    Right
-}
+/// Codeword of each symbol, as its bits and its length.
+pub type Codewords<S> = HashMap<S, (u64, u8)>;
 
-type Path = Vec<Sense>;
-type Codewords = HashMap<char, Path>;
-type CodewordsRev = HashMap<Path, char>;
+pub static COMPRESSED_FILE_EXTENSION: &str = "huff";
 
-pub static COMPRESSED_FILE_EXTENSION: &'static str = "huff";
+/// Longest codeword the decoder accepts. Unconstrained trees deeper than
+/// this, which need millions of very skewed symbols, are limited to it.
+pub const MAX_CODE_LENGTH: u8 = 32;
 
-impl HuffmanTree {
-    pub fn weight(self: &Self, frequencies: Frequencies) -> usize {
+impl<S: Symbol> HuffmanTree<S> {
+    pub fn weight(&self, frequencies: &Frequencies<S>) -> usize {
          match self {
-            HuffmanTree::Leaf(c) => {
-                let n = *c as usize;
-                frequencies[n]
-            },
+            HuffmanTree::Leaf(c) =>
+                frequencies.get(c).copied().unwrap_or(0),
             HuffmanTree::Node((s, t)) =>
                 s.weight(frequencies) + t.weight(frequencies)
          }
     }
 
-    fn fill_codewords_with_acc(self: &Self, codewords: &mut Codewords, current_path: Path) {
+    fn fill_code_lengths_with_acc(&self, code_lengths: &mut BTreeMap<S, u8>, depth: u8) {
         match self {
             HuffmanTree::Leaf(c) => {
-                let _ = codewords.insert(*c, current_path);
+                let _ = code_lengths.insert(*c, depth);
             }
             HuffmanTree::Node((s, t)) => {
-                let mut left_path = current_path.clone();
-                let mut right_path = current_path.clone();
-                left_path.push(Sense::Left);
-                right_path.push(Sense::Right);
-                s.fill_codewords_with_acc(codewords, left_path);
-                t.fill_codewords_with_acc(codewords, right_path);
+                s.fill_code_lengths_with_acc(code_lengths, depth + 1);
+                t.fill_code_lengths_with_acc(code_lengths, depth + 1);
             }
         }
     }
 
-    fn fill_codewords(self: &Self, codewords: &mut Codewords) {
-        self.fill_codewords_with_acc(codewords, Vec::new());
+    fn code_lengths(&self) -> CodeLengths<S> {
+        let mut code_lengths = BTreeMap::new();
+        // A lone leaf still needs a one bit codeword to be written at all
+        let depth = match self {
+            HuffmanTree::Leaf(_) => 1,
+            HuffmanTree::Node(_) => 0
+        };
+        self.fill_code_lengths_with_acc(&mut code_lengths, depth);
+        code_lengths.into_iter().collect()
     }
+}
 
-    fn fill_codewords_rev_with_acc(self: &Self, codewords_rev: &mut CodewordsRev, current_path: Path) {
-        match self {
-            HuffmanTree::Leaf(c) => {
-                let _ = codewords_rev.insert(current_path, *c);
-            },
-            HuffmanTree::Node((s, t)) => {
-                let mut left_path = current_path.clone();
-                let mut right_path = current_path.clone();
-                left_path.push(Sense::Left);
-                right_path.push(Sense::Right);
-                s.fill_codewords_rev_with_acc(codewords_rev, left_path);
-                t.fill_codewords_rev_with_acc(codewords_rev, right_path);
-            }
-        }
+/// Assigns canonical codewords: symbols are sorted by code length and then
+/// by value, and each one gets the next binary number of its length.
+/// Returns `(symbol, codeword, length)` in that canonical order.
+pub fn canonical_codes<S: Symbol>(code_lengths: &[(S, u8)]) -> Vec<(S, u64, u8)> {
+    let mut sorted: Vec<(u8, S)> = code_lengths.iter()
+        .map(|(c, len)| (*len, *c))
+        .collect();
+    sorted.sort();
+
+    let mut codes = Vec::with_capacity(sorted.len());
+    let mut code: u64 = 0;
+    let mut prev_len = 0;
+
+    for (len, c) in sorted {
+        code <<= len - prev_len;
+        prev_len = len;
+        codes.push((c, code, len));
+        code += 1;
     }
 
-    fn fill_codewords_rev(self: &Self, codewords: &mut CodewordsRev) {
-        self.fill_codewords_rev_with_acc(codewords, Vec::new());
-    }
+    codes
 }
 
-impl SerialisedHuffmanTree {
-    pub fn serialise(self: &Self, filepath: String) -> Result<(String, usize), String> {
-        let serial = postcard::to_allocvec(&self)
-            .expect("A valid serialisation");
-        let compressed_size = serial.len();
-        let compressed_filepath = format!("{filepath}.{COMPRESSED_FILE_EXTENSION}"); // XXX: Is there a more beatiful way of doing this?
-
-        std::fs::write(compressed_filepath.clone(), serial)
-            .expect("Writing correctly to the compressed file");
-
-        Ok((compressed_filepath, compressed_size))
-    }
+pub fn canonical_codewords<S: Symbol>(code_lengths: &[(S, u8)]) -> Codewords<S> {
+    canonical_codes(code_lengths)
+        .into_iter()
+        .map(|(c, code, len)| (c, (code, len)))
+        .collect()
+}
 
-    pub fn deserialise(compressed_filepath: String) -> (Self, String) {
-        let compressed = std::fs::read(&compressed_filepath).unwrap();
+pub fn code_lengths_to_u32<S: Symbol>(code_lengths: &[(S, u8)]) -> Vec<(u32, u8)> {
+    code_lengths.iter()
+        .map(|(c, len)| (c.to_u32(), *len))
+        .collect()
+}
 
-        let mut original_filepath_filebuf = std::path::PathBuf::from(&compressed_filepath);
-        original_filepath_filebuf.set_extension(""); // Removes `.huff` extension
-        let original_filepath = original_filepath_filebuf.into_os_string().into_string().unwrap();
+pub fn code_lengths_from_u32<S: Symbol>(code_lengths: &[(u32, u8)]) -> Option<CodeLengths<S>> {
+    code_lengths.iter()
+        .map(|(n, len)| match *len {
+            1..=MAX_CODE_LENGTH => S::from_u32(*n).map(|c| (c, *len)),
+            _ => None
+        })
+        .collect()
+}
 
-        println!("{} --> {}", compressed_filepath, original_filepath);
+impl<S: Symbol> HuffmanFreqTree<S> {
+    fn new(tree: HuffmanTree<S>, frequencies: &Frequencies<S>) -> Self {
+        let weight = tree.weight(frequencies);
+        HuffmanFreqTree { weight, tree }
+    }
 
-        let deserial: SerialisedHuffmanTree = postcard::from_bytes(&compressed)
-            .expect("Valid compressed file contents");
+    fn join(a: HuffmanFreqTree<S>, b: HuffmanFreqTree<S>) -> Self {
+        HuffmanFreqTree {
+            weight: a.weight + b.weight,
+            tree: HuffmanTree::Node((Box::new(a.tree), Box::new(b.tree)))
+        }
+    }
 
-        return (deserial, original_filepath)
+    pub fn weight(&self) -> usize {
+        self.weight
     }
 
-    fn encoded_chars_to_senses(self: &Self) -> Vec<Sense> {
-        let mut senses = Vec::new();
-        let mut i = 0;
+    fn from_frequencies(frequencies: &Frequencies<S>) -> Self {
+        let mut leaves: BinaryHeap<HuffmanFreqTree<S>> = frequencies.keys()
+            .map(|c| HuffmanFreqTree::new(HuffmanTree::Leaf(*c), frequencies))
+            .collect();
 
-        for n in self.encoded_chars.clone().into_iter() {
-            for offset in (0..=7).rev() {
-                if i > self.senses_count {
-                    break;
-                }
-                let sense = if ((n >> offset) & 1) == 1 { Sense::Right } else { Sense::Left };
-                senses.push(sense);
-                i += 1;
-            }
+        while leaves.len() > 1 {
+            let a = leaves.pop().unwrap();
+            let b = leaves.pop().unwrap();
+            leaves.push(HuffmanFreqTree::join(a, b));
         }
 
-        return senses
+        leaves.pop().unwrap()
     }
 }
 
-impl HuffmanFreqTree {
-    pub fn weight(self: &Self) -> usize {
-        self.tree.weight(self.frequencies)
+impl<S: Symbol> PartialOrd for HuffmanFreqTree<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl PartialOrd for HuffmanFreqTree {
+impl<S: Symbol> Ord for HuffmanFreqTree<S> {
     // Custom comparison function for min-heap
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(other.weight().cmp(&self.weight()))
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.weight().cmp(&self.weight())
     }
 }
 
-impl Ord for HuffmanFreqTree {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(&other).unwrap()
+/// Code lengths of a Huffman code for the given frequencies, limited as
+/// `options` asks.
+pub fn code_lengths_for<S: Symbol>(frequencies: &Frequencies<S>, options: HuffmanOptions) -> CodeLengths<S> {
+    if frequencies.is_empty() {
+        return Vec::new()
+    }
+
+    let unconstrained = HuffmanFreqTree::from_frequencies(frequencies).tree.code_lengths();
+    let max_len = options.max_code_length
+        .unwrap_or(MAX_CODE_LENGTH)
+        .min(MAX_CODE_LENGTH);
+
+    if unconstrained.iter().all(|(_, len)| *len <= max_len) {
+        unconstrained
+    } else {
+        let weights: Vec<usize> = frequencies.values().copied().collect();
+        let lengths = package_merge::limited_code_lengths(&weights, max_len);
+        frequencies.keys().copied().zip(lengths).collect()
     }
 }
 
-impl Huffman {
+/// Bits taken by the symbols counted in `frequencies` when coded with
+/// `code_lengths`, and the longest codeword among them.
+fn lengths_used<S: Symbol>(frequencies: &Frequencies<S>, code_lengths: &CodeLengths<S>) -> (usize, u8) {
+    code_lengths.iter()
+        .filter_map(|(c, len)| frequencies.get(c).map(|freq| (freq * *len as usize, *len)))
+        .fold((0, 0), |(bits, max), (freq_bits, len)| (bits + freq_bits, max.max(len)))
+}
 
-    fn text_to_flattened_senses(self: &Self, codewords: &Codewords) -> Vec<Sense> {
-        let mut paths = Vec::new();
-        for c in self.text.chars() {
-            let mut path = codewords.get(&c).unwrap().clone();
-            paths.append(&mut path)
+impl<S: Symbol> CodeLengthTally<S> {
+    fn new(options: HuffmanOptions) -> Self {
+        CodeLengthTally {
+            block: Frequencies::new(),
+            block_symbols: 0,
+            previous: None,
+            options,
+            symbols: 0,
+            bits: 0,
+            max: 0,
+            unconstrained_bits: 0,
+            unconstrained_max: 0
         }
-        return paths
     }
 
-    fn senses_to_encoded_chars(self: &Self, paths: &mut Vec<Sense>) -> (Vec<u8>, usize) {
-        let mut encoded_chars = Vec::with_capacity(paths.len() / 8);
+    fn push(&mut self, c: S) {
+        *self.block.entry(c).or_insert(0) += 1;
+        self.block_symbols += 1;
+        if self.block_symbols == BLOCK_SYMBOLS {
+            self.end_block();
+        }
+    }
 
-        let padding_count = 8 - paths.len() % 8;
-        for _ in 0..padding_count {
-            paths.push(Sense::Left);
+    fn end_block(&mut self) {
+        let frequencies = std::mem::take(&mut self.block);
+        let code_lengths = code_lengths_for(&frequencies, self.options);
+        let (_, code_lengths) = choose_table(&frequencies, code_lengths, self.previous.as_ref());
+        let unconstrained = HuffmanFreqTree::from_frequencies(&frequencies).tree.code_lengths();
+
+        let (bits, max) = lengths_used(&frequencies, &code_lengths);
+        let (unconstrained_bits, unconstrained_max) = lengths_used(&frequencies, &unconstrained);
+        self.bits += bits;
+        self.max = self.max.max(max);
+        self.unconstrained_bits += unconstrained_bits;
+        self.unconstrained_max = self.unconstrained_max.max(unconstrained_max);
+
+        self.symbols += self.block_symbols;
+        self.block_symbols = 0;
+        self.previous = Some(code_lengths);
+    }
+
+    fn report(mut self) -> CodeLengthReport {
+        if self.block_symbols > 0 {
+            self.end_block();
+        }
+        let average = |bits: usize| bits as f64 / self.symbols.max(1) as f64;
+
+        CodeLengthReport {
+            average: average(self.bits),
+            max: self.max,
+            unconstrained_average: average(self.unconstrained_bits),
+            unconstrained_max: self.unconstrained_max
         }
+    }
+}
+
+impl Huffman {
+    /// Counts the symbols of everything `reader` yields, block by block as
+    /// `compress` codes them. The input is coded as text if it all turns
+    /// out to be valid UTF-8.
+    pub fn from_reader(mut reader: impl Read, options: HuffmanOptions) -> Result<Self, HuffmanError> {
+        let mut bytes = CodeLengthTally::new(options);
+        let mut chars = Some(CodeLengthTally::new(options));
+        let mut pending = Vec::new();
+        let mut buffer = vec![0; 1 << 16];
+
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into())
+            };
+
+            for b in &buffer[..n] {
+                bytes.push(*b);
+            }
 
-        // Adding each sense bit by bit
-        let mut curr_path = 0;
-        while curr_path < paths.len() {
-            let mut n: u8 = 0;
-            for offset in (0..=7).rev() {
-                let bit = match paths[curr_path] {
-                    Sense::Left  => 0,
-                    Sense::Right => 1
-                };
-                n |= bit << offset; // Add bit representing the sense to take
-                curr_path += 1;
+            if let Some(tally) = &mut chars {
+                let counted = decode_utf8(&mut pending, &buffer[..n], |c| {
+                    tally.push(c);
+                    Ok(())
+                });
+                if counted.is_err() {
+                    chars = None;
+                }
             }
-            encoded_chars.push(n);
         }
 
-        assert!(paths.len() == curr_path);
-        return (encoded_chars, curr_path - padding_count)
+        let huf = match chars {
+            Some(tally) if pending.is_empty() =>
+                Huffman { mode: SymbolMode::Text, report: tally.report() },
+            _ => Huffman { mode: SymbolMode::Binary, report: bytes.report() }
+        };
+
+        Ok(huf)
     }
 
-    pub fn compress(self: &Self) -> SerialisedHuffmanTree {
-        let mut codewords: Codewords = HashMap::new();
-        self.freq_tree.tree.fill_codewords(&mut codewords);
+    pub fn mode(&self) -> SymbolMode {
+        self.mode
+    }
 
-        let mut senses = self.text_to_flattened_senses(&codewords);
+    /// Lengths of the codewords `compress` gives the symbols with the
+    /// options this was counted with, in static blocks whose runs are
+    /// left as they are.
+    pub fn code_length_report(&self) -> CodeLengthReport {
+        self.report
+    }
 
-        let (encoded_chars, senses_count) = self.senses_to_encoded_chars(&mut senses);
+    /// Counts the symbols of a file, returning its code and its size.
+    pub fn from_file(filepath: &String, options: HuffmanOptions) -> Result<(Self, usize), HuffmanError> {
+        let file = File::open(filepath)?;
+        let contents_len = file.metadata()?.len() as usize;
 
-        return SerialisedHuffmanTree {
-            tree: self.freq_tree.tree.clone(),
-            senses_count,
-            encoded_chars
+        if contents_len == 0 {
+            return Err(HuffmanError::EmptyInput)
         }
+
+        let huf = Huffman::from_reader(BufReader::new(file), options)?;
+
+        Ok((huf, contents_len))
     }
 
-    fn reconstruct_text(tree: &HuffmanTree, senses: Vec<Sense>) -> String {
-        let mut codewords_rev: CodewordsRev = HashMap::new();
-        tree.fill_codewords_rev(&mut codewords_rev);
+    /// Compresses the file this code was counted from, saving it next to
+    /// the original. Each block of the file gets a code of its own. Returns
+    /// where it was saved and its size.
+    ///
+    /// Should coding make the file bigger, it is stored as is instead, so
+    /// the result is never larger than `stream::max_compressed_len`.
+    pub fn compress(&self, filepath: String, options: HuffmanOptions) -> Result<(String, usize), HuffmanError> {
+        let compressed_filepath = format!("{filepath}.{COMPRESSED_FILE_EXTENSION}"); // XXX: Is there a more beatiful way of doing this?
 
-        let mut reconstructed_text = String::new();
+        let compress = |options: HuffmanOptions| -> io::Result<(usize, u64)> {
+            let input = File::open(&filepath)?;
+            let metadata = input.metadata()?;
+            let info = file_info(&filepath, &metadata, options);
+            let max_len = max_compressed_len(metadata.len(), &info);
 
-        let mut current_path: Vec<Sense> = Vec::new();
-        for sense in senses {
-            current_path.push(sense);
-            if let Some(c) = codewords_rev.get(&current_path) {
-                reconstructed_text.push(*c);
-                current_path.clear();
-            }
+            let output = BufWriter::new(File::create(&compressed_filepath)?);
+            let output = self.encode(BufReader::new(input), output, info, options)?;
+
+            Ok((output.get_ref().metadata()?.len() as usize, max_len))
+        };
+
+        let (mut compressed_size, max_len) = compress(options)?;
+        if compressed_size as u64 > max_len {
+            let stored = HuffmanOptions { method: CodingMethod::Stored, ..options };
+            (compressed_size, _) = compress(stored)?;
         }
 
-        return reconstructed_text
+        Ok((compressed_filepath, compressed_size))
     }
 
-    pub fn decompress(deserial: SerialisedHuffmanTree) -> Option<String> {
-        let senses = deserial.encoded_chars_to_senses();
-        let reconstructed_text = Huffman::reconstruct_text(&deserial.tree, senses);
+    /// Size `compress` would save the file at with `options`, without
+    /// saving anything.
+    pub fn compressed_len(&self, filepath: &String, options: HuffmanOptions) -> Result<u64, HuffmanError> {
+        let input = File::open(filepath)?;
+        let metadata = input.metadata()?;
+        let info = file_info(filepath, &metadata, options);
+        let max_len = max_compressed_len(metadata.len(), &info);
 
-        Some(reconstructed_text)
+        let counter = self.encode(BufReader::new(input), ByteCounter(0), info, options)?;
+
+        // Stored as is, it takes exactly `max_len`
+        Ok(counter.0.min(max_len))
     }
 
-    pub fn from_file(filepath: &String) -> Result<(Self, usize), String> {
-        let text = std::fs::read_to_string(filepath).unwrap();
-        let text_len = text.len();
+    /// Compresses everything `input` yields into `output`, with the header
+    /// recording `info`.
+    fn encode<W: Write>(&self, mut input: impl Read, output: W, info: FileInfo,
+                        options: HuffmanOptions) -> io::Result<W> {
+        let mut encoder = HuffmanEncoder::new(output, self.mode(), info, options)?;
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()
+    }
 
-        if text_len == 0 {
-            return Err("No content to compress".to_string())
+    /// Compresses `bytes` held in memory, into exactly what `compress`
+    /// saves for a file with the same contents and no metadata recorded.
+    pub fn compress_bytes(bytes: &[u8], options: HuffmanOptions) -> Result<Vec<u8>, HuffmanError> {
+        if bytes.is_empty() {
+            return Err(HuffmanError::EmptyInput)
         }
 
-        let mut frequencies = [0; 256];
-        for c in text.chars() {
-            frequencies[c as usize] += 1;
+        let huffman = Huffman::from_reader(bytes, options)?;
+        let info = FileInfo { len: Some(bytes.len() as u64), ..FileInfo::default() };
+        let max_len = max_compressed_len(bytes.len() as u64, &info);
+
+        let mut compressed = huffman.encode(bytes, Vec::new(), info.clone(), options)?;
+        if compressed.len() as u64 > max_len {
+            let stored = HuffmanOptions { method: CodingMethod::Stored, ..options };
+            compressed = huffman.encode(bytes, Vec::new(), info, stored)?;
         }
 
-        let mut leaves: BinaryHeap<HuffmanFreqTree> = BinaryHeap::new();
+        Ok(compressed)
+    }
+
+    /// Compresses the file into a gzip member saved next to it, which
+    /// `gzip -d` and `zcat` can read. Returns where it was saved and its
+    /// size.
+    pub fn compress_gzip(filepath: String, options: HuffmanOptions) -> Result<(String, usize), HuffmanError> {
+        let gzip_filepath = format!("{filepath}.{GZIP_FILE_EXTENSION}");
+
+        let input = File::open(&filepath)?;
+        let info = file_info(&filepath, &input.metadata()?, options);
+
+        let output = BufWriter::new(File::create(&gzip_filepath)?);
+        let mut encoder = GzipEncoder::new(output, &info, options)?;
+        io::copy(&mut BufReader::new(input), &mut encoder)?;
+        let output = encoder.finish()?;
+
+        Ok((gzip_filepath, output.get_ref().metadata()?.len() as usize))
+    }
+
+    /// Compresses `bytes` held in memory into a gzip member.
+    pub fn gzip_bytes(bytes: &[u8], options: HuffmanOptions) -> Result<Vec<u8>, HuffmanError> {
+        let mut encoder = GzipEncoder::new(Vec::new(), &FileInfo::default(), options)?;
+        encoder.write_all(bytes)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Recovers the original contents from compressed bytes held in memory,
+    /// whether they come from `compress_bytes` or a compressed file.
+    pub fn decompress_bytes(compressed: &[u8]) -> Result<Vec<u8>, HuffmanError> {
+        let mut decoder = HuffmanDecoder::new(compressed)?;
+        let mut bytes = Vec::new();
+        decoder.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Whether the file starts like a compressed file, whatever its name.
+    pub fn is_compressed(filepath: &String) -> bool {
+        File::open(filepath).is_ok_and(container::has_magic)
+    }
 
-        for (i, freq) in frequencies.iter().enumerate() {
-            if *freq > 0 {
-                let c = char::from_u32(i as u32).unwrap();
-                leaves.push(HuffmanFreqTree {
-                    frequencies,
-                    tree: HuffmanTree::Leaf(c)
-                })
+    /// Recovers the original file next to the compressed one, returning
+    /// where it was saved. With `restore_metadata`, the file gets back the
+    /// name, modification time and permissions recorded in the header. The
+    /// contents are written to a temporary file first and only replace an
+    /// existing file once they check out. A name from the header never
+    /// replaces a file, decompressing fails if one has it already.
+    pub fn decompress(compressed_filepath: String, restore_metadata: bool) -> Result<String, HuffmanError> {
+        let input = BufReader::new(File::open(&compressed_filepath)?);
+        let mut decoder = HuffmanDecoder::new(input)?;
+
+        let compressed_path = Path::new(&compressed_filepath);
+        let recorded_name = decoder.info().file_name().filter(|_| restore_metadata).map(String::from);
+        let original_path = match &recorded_name {
+            Some(name) => compressed_path.with_file_name(name),
+            None if compressed_path.extension().is_some_and(|ext| ext == COMPRESSED_FILE_EXTENSION) =>
+                compressed_path.with_extension(""), // Removes `.huff` extension
+            None => {
+                let mut original_path = compressed_path.to_path_buf();
+                original_path.as_mut_os_string().push(".out");
+                original_path
             }
-        }
+        };
+        let original_filepath = original_path.into_os_string().into_string()
+            .map_err(|_| HuffmanError::Format("The file name is not valid UTF-8".to_owned()))?;
 
-        while leaves.len() > 1 {
-            let a = leaves.pop().unwrap().tree;
-            let b = leaves.pop().unwrap().tree;
-            let node = HuffmanFreqTree {
-                frequencies,
-                tree: HuffmanTree::Node((Box::new(a), Box::new(b)))
-            };
-            leaves.push(node);
+        let partial_filepath = format!("{original_filepath}.part");
+
+        // Taken before decoding, so that nothing else gets the name meanwhile
+        if recorded_name.is_some() {
+            File::create_new(&original_filepath)?;
         }
 
-        let huf = Huffman {
-            freq_tree: leaves.pop().unwrap(),
-            text
+        let mut decompress = || -> io::Result<()> {
+            let mut output = BufWriter::new(File::create(&partial_filepath)?);
+            io::copy(&mut decoder, &mut output)?;
+
+            let output = output.into_inner().map_err(|err| err.into_error())?;
+            if restore_metadata {
+                decoder.info().apply_to(&output)?;
+            }
+            Ok(())
         };
 
-        return Ok((huf, text_len))
+        if let Err(err) = decompress() {
+            let _ = std::fs::remove_file(&partial_filepath);
+            if recorded_name.is_some() {
+                let _ = std::fs::remove_file(&original_filepath);
+            }
+            return Err(err.into())
+        }
+        std::fs::rename(&partial_filepath, &original_filepath)?;
 
+        Ok(original_filepath)
     }
 }
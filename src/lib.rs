@@ -0,0 +1,58 @@
+//! Huffman compression of files and streams, and the format they are
+//! saved in.
+//!
+//! - `huffman` builds codes from the symbols of an input and compresses
+//!   and decompresses whole files, or bytes held in memory.
+//! - `stream` compresses and decompresses over `Read` and `Write`, with
+//!   any of the entropy coders in `codec`.
+//! - `transform` holds reversible transforms of blocks, such as the
+//!   Burrows-Wheeler transform, that chain into pipelines.
+//! - `container` is the header of compressed files, `archive` the format
+//!   holding whole directories, and `gzip` writes standard gzip instead.
+//!
+//! ```no_run
+//! use rust_huffman_egui::{Huffman, HuffmanOptions};
+//!
+//! let options = HuffmanOptions::default();
+//! let filepath = "examples/simple.txt".to_string();
+//! let (huffman, _) = Huffman::from_file(&filepath, options)?;
+//! let (compressed_filepath, _) = huffman.compress(filepath, options)?;
+//! Huffman::decompress(compressed_filepath, true)?;
+//! # Ok::<(), rust_huffman_egui::HuffmanError>(())
+//! ```
+//!
+//! The graphical application is behind the `gui` feature, on by default.
+
+mod adaptive;
+pub mod archive;
+// Public only for the benches
+#[doc(hidden)]
+pub mod bits;
+mod block;
+mod block_sort;
+pub mod codec;
+pub mod container;
+mod crc32;
+// Public only for the benches
+#[doc(hidden)]
+pub mod decode_table;
+pub mod error;
+pub mod gzip;
+pub mod huffman;
+mod lz77;
+// Public only for the benches
+#[doc(hidden)]
+pub mod package_merge;
+mod parallel;
+mod range_coder;
+mod run_length;
+mod stored;
+pub mod stream;
+mod tans;
+pub mod transform;
+
+pub use codec::{EntropyCoder, CODERS};
+pub use error::HuffmanError;
+pub use gzip::GzipEncoder;
+pub use huffman::{CodingMethod, Huffman, HuffmanOptions, Symbol, SymbolMode};
+pub use stream::{HuffmanDecoder, HuffmanEncoder};
@@ -0,0 +1,330 @@
+//! LZ77 followed by Huffman coding, the scheme of DEFLATE.
+//!
+//! The input is cut into blocks of `LZ_BLOCK_BYTES` bytes, and each block
+//! into tokens: literal bytes, and matches repeating up to `MAX_MATCH`
+//! bytes found at most `WINDOW` bytes back in the same block. Lengths and
+//! distances are turned into codes with extra bits, as in DEFLATE.
+//! Literals and length codes share an alphabet, distance codes have one of
+//! their own, and both are Huffman coded with the codes of the block.
+//!
+//! The header of a block holds the code lengths of both alphabets, and
+//! the coded tokens follow.
+
+use std::io::{self, Read};
+
+use serde::{Serialize, Deserialize};
+
+use crate::bits::{BitReader, BitWriter};
+use crate::block::BlockCoder;
+use crate::decode_table::DecodeTable;
+use crate::huffman::{canonical_codewords, code_lengths_for, code_lengths_from_u32, code_lengths_to_u32,
+                     CodeLengths, Frequencies, HuffmanOptions, MAX_CODE_LENGTH};
+use crate::stream::{invalid_data, read_frame, SymbolDecoder};
+
+/// Bytes in every block but the last.
+pub const LZ_BLOCK_BYTES: usize = 1 << 20;
+
+/// How far back a match can start.
+pub const WINDOW: usize = 1 << 15;
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+/// Candidates tried before settling for the longest match so far.
+const MAX_CHAIN: usize = 128;
+
+const HASH_BITS: u32 = 15;
+
+/// Symbol of the first length code in the literal and length alphabet,
+/// the ones below being literals and the end of block of DEFLATE.
+pub const FIRST_LENGTH_SYMBOL: u16 = 257;
+
+/// Shortest length of each length code, then its number of extra bits.
+pub const LENGTH_BASES: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                     35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                         3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// Shortest distance of each distance code, then its number of extra bits.
+pub const DISTANCE_BASES: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                       257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                       8193, 12289, 16385, 24577];
+pub const DISTANCE_EXTRA_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                           7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Largest payload a block can have: every byte a literal with the
+/// longest codeword.
+const MAX_BLOCK_BYTES: usize = LZ_BLOCK_BYTES * MAX_CODE_LENGTH as usize / 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 }
+}
+
+/// The code of a length or distance among `bases`, with the extra bits
+/// that tell which value of the code it is.
+pub fn code_of(value: u16, bases: &[u16], extra_bits: &[u8]) -> (usize, u8, u16) {
+    let code = bases.partition_point(|base| *base <= value) - 1;
+    (code, extra_bits[code], value - bases[code])
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let n = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    (n.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Finds matches through chains of the earlier positions with the same
+/// hash of their first `MIN_MATCH` bytes.
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    head: Vec<u32>,
+    previous: Vec<u32>
+}
+
+impl<'a> MatchFinder<'a> {
+    const NONE: u32 = u32::MAX;
+
+    fn new(data: &'a [u8]) -> Self {
+        MatchFinder {
+            data,
+            head: vec![Self::NONE; 1 << HASH_BITS],
+            previous: vec![Self::NONE; data.len()]
+        }
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH <= self.data.len() {
+            let h = hash(&self.data[position..]);
+            self.previous[position] = self.head[h];
+            self.head[h] = position as u32;
+        }
+    }
+
+    /// The longest match for the bytes at `position`, as its length and
+    /// distance, or a length of 0.
+    fn longest_match(&self, position: usize) -> (usize, usize) {
+        let data = self.data;
+        if position + MIN_MATCH > data.len() {
+            return (0, 0)
+        }
+        let max_length = MAX_MATCH.min(data.len() - position);
+
+        let (mut best_length, mut best_distance) = (0, 0);
+        let mut candidate = self.head[hash(&data[position..])];
+        for _ in 0..MAX_CHAIN {
+            if candidate == Self::NONE || position - candidate as usize > WINDOW {
+                break
+            }
+            let start = candidate as usize;
+
+            if data[start + best_length.min(max_length - 1)] == data[position + best_length.min(max_length - 1)] {
+                let length = data[start..].iter().zip(&data[position..position + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    (best_length, best_distance) = (length, position - start);
+                    if length == max_length {
+                        break
+                    }
+                }
+            }
+            candidate = self.previous[start];
+        }
+
+        if best_length >= MIN_MATCH { (best_length, best_distance) } else { (0, 0) }
+    }
+}
+
+/// Cuts `data` into literals and matches. A match is only taken when the
+/// next byte does not start a longer one.
+pub fn tokens(data: &[u8]) -> Vec<Token> {
+    let mut finder = MatchFinder::new(data);
+    let mut tokens = Vec::new();
+
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = finder.longest_match(position);
+        finder.insert(position);
+
+        if length == 0 || finder.longest_match(position + 1).0 > length {
+            tokens.push(Token::Literal(data[position]));
+            position += 1;
+            continue
+        }
+
+        tokens.push(Token::Match { length: length as u16, distance: distance as u16 });
+        for skipped in position + 1..position + length {
+            finder.insert(skipped);
+        }
+        position += length;
+    }
+
+    tokens
+}
+
+/// How many times each literal or length code and each distance code
+/// comes up among `tokens`, and how many extra bits the lengths and
+/// distances take on top of their codes.
+pub fn token_frequencies(tokens: &[Token]) -> (Frequencies<u16>, Frequencies<u16>, usize) {
+    let mut literal_frequencies = Frequencies::new();
+    let mut distance_frequencies = Frequencies::new();
+    let mut extra_bits = 0;
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => *literal_frequencies.entry(b as u16).or_insert(0) += 1,
+            Token::Match { length, distance } => {
+                let (length_code, length_bits, _) = code_of(length, &LENGTH_BASES, &LENGTH_EXTRA_BITS);
+                *literal_frequencies.entry(FIRST_LENGTH_SYMBOL + length_code as u16).or_insert(0) += 1;
+                let (distance_code, distance_bits, _) = code_of(distance, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS);
+                *distance_frequencies.entry(distance_code as u16).or_insert(0) += 1;
+                extra_bits += (length_bits + distance_bits) as usize;
+            }
+        }
+    }
+    (literal_frequencies, distance_frequencies, extra_bits)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LzBlockHeader {
+    /// Code lengths of the literal and length alphabet, as in
+    /// `code_lengths_to_u32`
+    literal_lengths: Vec<(u32, u8)>,
+    /// Code lengths of the distance alphabet
+    distance_lengths: Vec<(u32, u8)>,
+    token_count: usize,
+    bit_count: usize
+}
+
+/// Codes the tokens of each block with codes of its own.
+pub struct LzEncoder {
+    options: HuffmanOptions
+}
+
+impl LzEncoder {
+    pub fn new(options: HuffmanOptions) -> Self {
+        LzEncoder { options }
+    }
+}
+
+impl BlockCoder<u8> for LzEncoder {
+    type Header = LzBlockHeader;
+
+    const BLOCK_LEN: usize = LZ_BLOCK_BYTES;
+
+    fn code_block(&self, data: &[u8]) -> io::Result<(LzBlockHeader, Vec<u8>)> {
+        let tokens = tokens(data);
+
+        let (literal_frequencies, distance_frequencies, _) = token_frequencies(&tokens);
+        let literal_lengths = code_lengths_for(&literal_frequencies, self.options);
+        let distance_lengths = code_lengths_for(&distance_frequencies, self.options);
+        let literal_codewords = canonical_codewords(&literal_lengths);
+        let distance_codewords = canonical_codewords(&distance_lengths);
+
+        let mut bits = BitWriter::new();
+        for token in &tokens {
+            match *token {
+                Token::Literal(b) => {
+                    let (code, len) = literal_codewords[&(b as u16)];
+                    bits.write(code, len as u32);
+                },
+                Token::Match { length, distance } => {
+                    let (length_code, extra_bits, extra) = code_of(length, &LENGTH_BASES, &LENGTH_EXTRA_BITS);
+                    let (code, len) = literal_codewords[&(FIRST_LENGTH_SYMBOL + length_code as u16)];
+                    bits.write(code, len as u32);
+                    bits.write(extra as u64, extra_bits as u32);
+
+                    let (distance_code, extra_bits, extra) = code_of(distance, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS);
+                    let (code, len) = distance_codewords[&(distance_code as u16)];
+                    bits.write(code, len as u32);
+                    bits.write(extra as u64, extra_bits as u32);
+                }
+            }
+        }
+        let (bytes, bit_count) = bits.finish();
+
+        let header = LzBlockHeader {
+            literal_lengths: code_lengths_to_u32(&literal_lengths),
+            distance_lengths: code_lengths_to_u32(&distance_lengths),
+            token_count: tokens.len(),
+            bit_count
+        };
+        Ok((header, bytes))
+    }
+}
+
+pub struct LzDecoder;
+
+/// Reads a length or distance code's extra bits and adds them to its base.
+fn read_value(code: u16, bases: &[u16], extra_bits: &[u8], bits: &mut BitReader) -> io::Result<usize> {
+    let code = code as usize;
+    if code >= bases.len() {
+        return Err(invalid_data(format!("Invalid length or distance code {code}")))
+    }
+    let extra = bits.peek(extra_bits[code] as u32);
+    bits.consume(extra_bits[code] as u32);
+    Ok(bases[code] as usize + extra as usize)
+}
+
+impl SymbolDecoder for LzDecoder {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let Some(header): Option<LzBlockHeader> = read_frame(&mut *reader)? else {
+            return Ok(false)
+        };
+
+        let len = header.bit_count.div_ceil(8);
+        if len > MAX_BLOCK_BYTES {
+            return Err(invalid_data(format!("Block of {len} bytes is too long")))
+        }
+        if header.token_count > LZ_BLOCK_BYTES {
+            return Err(invalid_data(format!("Block of {} tokens is too long", header.token_count)))
+        }
+
+        let literal_lengths: CodeLengths<u16> = code_lengths_from_u32(&header.literal_lengths)
+            .ok_or_else(|| invalid_data("Invalid code lengths"))?;
+        let distance_lengths: CodeLengths<u16> = code_lengths_from_u32(&header.distance_lengths)
+            .ok_or_else(|| invalid_data("Invalid code lengths"))?;
+        let literals = DecodeTable::new(&literal_lengths)?;
+        let distances = DecodeTable::new(&distance_lengths)?;
+
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+        let mut bits = BitReader::new(&bytes);
+
+        let start = out.len();
+        for _ in 0..header.token_count {
+            let symbol = literals.decode(&mut bits)
+                .ok_or_else(|| invalid_data("Invalid codeword in compressed stream"))?;
+            if let Ok(b) = u8::try_from(symbol) {
+                out.push(b);
+                continue
+            }
+
+            let length_code = symbol.checked_sub(FIRST_LENGTH_SYMBOL)
+                .ok_or_else(|| invalid_data(format!("Invalid symbol {symbol}")))?;
+            let length = read_value(length_code, &LENGTH_BASES, &LENGTH_EXTRA_BITS, &mut bits)?;
+
+            let distance_code = distances.decode(&mut bits)
+                .ok_or_else(|| invalid_data("Invalid codeword in compressed stream"))?;
+            let distance = read_value(distance_code, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS, &mut bits)?;
+
+            if distance > out.len() - start {
+                return Err(invalid_data("Match starts before the block"))
+            }
+            if out.len() - start + length > LZ_BLOCK_BYTES {
+                return Err(invalid_data("Block decodes to too many bytes"))
+            }
+            // One byte at a time, as a match may overlap what it repeats
+            let from = out.len() - distance;
+            for i in 0..length {
+                out.push(out[from + i]);
+            }
+        }
+
+        if bits.position() != header.bit_count {
+            return Err(invalid_data("Block does not decode to its length"))
+        }
+
+        Ok(true)
+    }
+}
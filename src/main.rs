@@ -1,11 +1,14 @@
+use rust_huffman_egui::archive::{self, ArchiveEntry, ArchiveReader, EntryKind};
+use rust_huffman_egui::codec::{HuffmanCoder, RangeCoder};
+use rust_huffman_egui::huffman::CodeLengthReport;
+use rust_huffman_egui::{CodingMethod, EntropyCoder, Huffman, HuffmanError, HuffmanOptions, CODERS};
 
-mod huffman;
-use std::io::Write;
-
-use huffman::{Huffman, SerialisedHuffmanTree};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
 
 use eframe::egui;
-use tinyfiledialogs;
 
 fn main() -> Result<(), eframe::Error> {
     let options = eframe::NativeOptions {
@@ -13,14 +16,13 @@ fn main() -> Result<(), eframe::Error> {
         ..Default::default()
     };
 
-    return
-        eframe::run_native(
-            "Hello egui",
-            options,
-            Box::new(|_| {
-                Box::<Application>::default()
-            })
-        )
+    eframe::run_native(
+        "Hello egui",
+        options,
+        Box::new(|_| {
+            Box::<Application>::default()
+        })
+    )
 }
 
 struct SizeComparison {
@@ -28,77 +30,424 @@ struct SizeComparison {
     compressed: usize
 }
 
+/// What a compression or decompression running in the background ended
+/// with.
+enum Outcome {
+    Compressed {
+        filepath: String,
+        sizes: SizeComparison,
+        code_length_report: Option<CodeLengthReport>,
+        /// Size the file would take with each coder
+        coder_sizes: Vec<(&'static str, u64)>
+    },
+    Decompressed(String),
+    ArchiveCreated {
+        filepath: String,
+        sizes: SizeComparison
+    },
+    ArchiveListed(ArchiveListing),
+    Extracted(String),
+    /// The checksum did not match, nothing was written
+    Corrupted(String),
+    Failed(String)
+}
+
+/// The contents of an archive that was opened.
+struct ArchiveListing {
+    filepath: String,
+    entries: Vec<ArchiveEntry>
+}
+
 struct Application {
     status: String,
-    size_comparison: Option<SizeComparison>
+    corrupted: bool,
+    job: Option<Receiver<Outcome>>,
+    size_comparison: Option<SizeComparison>,
+    code_length_report: Option<CodeLengthReport>,
+    coder_sizes: Vec<(&'static str, u64)>,
+    archive: Option<ArchiveListing>,
+    coder: &'static dyn EntropyCoder,
+    limit_code_length: bool,
+    max_code_length: u8,
+    method: CodingMethod,
+    keep_metadata: bool,
+    share_code: bool,
+    run_length: bool,
+    gzip: bool
 }
 
 impl Default for Application {
     fn default() -> Self {
         Self {
             status: "Compress or decompress a Huffman encoded file".to_owned(),
-            size_comparison: None
+            corrupted: false,
+            job: None,
+            size_comparison: None,
+            code_length_report: None,
+            coder_sizes: Vec::new(),
+            archive: None,
+            coder: CODERS[0],
+            limit_code_length: false,
+            max_code_length: 15,
+            method: CodingMethod::Static,
+            keep_metadata: true,
+            share_code: false,
+            run_length: false,
+            gzip: false
+        }
+    }
+}
+
+impl Application {
+    fn huffman_options(&self) -> HuffmanOptions {
+        let method = method_for(self.coder, self.method);
+
+        HuffmanOptions {
+            coder: self.coder,
+            max_code_length: (self.limit_code_length && self.limits_code_length())
+                .then_some(self.max_code_length),
+            method,
+            threads: 0,
+            store_metadata: self.keep_metadata,
+            run_length: self.run_length
+        }
+    }
+
+    /// Whether the output is coded with Huffman codes whose length can be
+    /// limited.
+    fn limits_code_length(&self) -> bool {
+        self.gzip || (self.coder.uses_code_lengths() && method_for(self.coder, self.method) == CodingMethod::Static)
+    }
+
+    /// Runs `work` on a thread of its own, so that the window stays
+    /// responsive. Its outcome is picked up by `poll_job`.
+    fn start_job(&mut self, status: String, work: impl FnOnce() -> Outcome + Send + 'static) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(work());
+        });
+
+        self.status = status;
+        self.corrupted = false;
+        self.job = Some(receiver);
+    }
+
+    fn poll_job(&mut self, ctx: &egui::Context) {
+        let Some(job) = &self.job else {
+            return
+        };
+
+        let outcome = match job.try_recv() {
+            Ok(outcome) => outcome,
+            Err(TryRecvError::Empty) => {
+                ctx.request_repaint_after(Duration::from_millis(100));
+                return
+            },
+            Err(TryRecvError::Disconnected) => Outcome::Failed("The job stopped unexpectedly".to_owned())
+        };
+        self.job = None;
+
+        match outcome {
+            Outcome::Compressed { filepath, sizes, code_length_report, coder_sizes } => {
+                self.status = format!("Saved compressed file to {}", filepath);
+                self.size_comparison = Some(sizes);
+                self.code_length_report = code_length_report;
+                self.coder_sizes = coder_sizes;
+            },
+            Outcome::Decompressed(filepath) => {
+                self.status = format!("Decompressed to {}", filepath);
+            },
+            Outcome::ArchiveCreated { filepath, sizes } => {
+                self.status = format!("Saved archive to {}", filepath);
+                self.size_comparison = Some(sizes);
+                self.code_length_report = None;
+                self.coder_sizes = Vec::new();
+            },
+            Outcome::ArchiveListed(listing) => {
+                self.status = format!("Archive {} holds {} entries", listing.filepath, listing.entries.len());
+                self.archive = Some(listing);
+            },
+            Outcome::Extracted(filepath) => {
+                self.status = format!("Extracted to {}", filepath);
+            },
+            Outcome::Corrupted(err) => {
+                self.status = err;
+                self.corrupted = true;
+            },
+            Outcome::Failed(err) => {
+                self.status = err;
+            }
         }
     }
 }
 
-fn compress_with_filepath(app: &mut Application, filepath: String) {
-    app.status = format!("Compressing {}", filepath);
+/// `method` if `coder` has it, or else the first method it has.
+fn method_for(coder: &dyn EntropyCoder, method: CodingMethod) -> CodingMethod {
+    match coder.methods() {
+        methods if methods.contains(&method) => method,
+        methods => methods[0]
+    }
+}
+
+/// Tells contents that turned out corrupted apart from other failures.
+fn failure(err: HuffmanError) -> Outcome {
+    match err {
+        HuffmanError::Corrupted(_) => Outcome::Corrupted(err.to_string()),
+        err => Outcome::Failed(err.to_string())
+    }
+}
+
+fn compress_with_filepath(filepath: String, options: HuffmanOptions) -> Outcome {
+    let (huffman, text_size) = match Huffman::from_file(&filepath, options) {
+        Ok(counted) => counted,
+        Err(err) => return failure(err)
+    };
 
-    match Huffman::from_file(&filepath) {
-        Ok((huffman, text_size)) => {
-            let compressed = huffman.compress();
-            let (serialised_filepath, compressed_size) = compressed.serialise(filepath).unwrap();
-            app.status = format!("Saved compressed file to {}", serialised_filepath);
-            app.size_comparison = Some (SizeComparison {
+    // An adaptive code changes all along, and shortened runs leave other
+    // symbols to code than those counted
+    let code_length_report = (options.method == CodingMethod::Static && options.coder.uses_code_lengths()
+                              && !options.run_length)
+        .then(|| huffman.code_length_report());
+
+    // Huffman and range coding are compared with each other, the other
+    // one only counting its output
+    let compared: [&'static dyn EntropyCoder; 2] = [&HuffmanCoder, &RangeCoder];
+    let other_sizes: Vec<_> = compared.iter()
+        .filter(|_| compared.iter().any(|coder| coder.id() == options.coder.id()))
+        .filter(|coder| coder.id() != options.coder.id())
+        .filter_map(|coder| {
+            let options = HuffmanOptions { coder: *coder, method: method_for(*coder, options.method), ..options };
+            huffman.compressed_len(&filepath, options).ok().map(|size| (coder.name(), size))
+        })
+        .collect();
+
+    match huffman.compress(filepath, options) {
+        Ok((serialised_filepath, compressed_size)) => Outcome::Compressed {
+            filepath: serialised_filepath,
+            sizes: SizeComparison {
                 original: text_size,
                 compressed: compressed_size
-            });
+            },
+            code_length_report,
+            coder_sizes: std::iter::once((options.coder.name(), compressed_size as u64))
+                .chain(other_sizes)
+                .collect()
         },
-        Err(err) => {
-            app.status = err;
-        }
+        Err(err) => failure(err)
+    }
+}
+
+fn gzip_with_filepath(filepath: String, options: HuffmanOptions) -> Outcome {
+    let original = match std::fs::metadata(&filepath) {
+        Ok(metadata) => metadata.len() as usize,
+        Err(err) => return failure(err.into())
+    };
+
+    match Huffman::compress_gzip(filepath, options) {
+        Ok((gzip_filepath, compressed_size)) => Outcome::Compressed {
+            filepath: gzip_filepath,
+            sizes: SizeComparison {
+                original,
+                compressed: compressed_size
+            },
+            code_length_report: None,
+            coder_sizes: Vec::new()
+        },
+        Err(err) => failure(err)
     }
 }
 
-fn decompress_with_filepath(app: &mut Application, filepath: String) {
-    app.status = format!("Decompressing {}", filepath);
+fn decompress_with_filepath(filepath: String, restore_metadata: bool) -> Outcome {
+    match Huffman::decompress(filepath, restore_metadata) {
+        Ok(original_filepath) => Outcome::Decompressed(original_filepath),
+        Err(err) => failure(err)
+    }
+}
 
-    let (deserialised, original_filepath) = SerialisedHuffmanTree::deserialise(filepath);
+fn archive_directory(dirpath: String, options: HuffmanOptions, share_code: bool) -> Outcome {
+    let archive_filepath = format!("{dirpath}.{}", archive::ARCHIVE_FILE_EXTENSION);
 
-    let original_text = Huffman::decompress(deserialised).unwrap();
-    let mut original_file = std::fs::File::create(original_filepath.clone()).unwrap();
+    let archived = archive::create_archive(Path::new(&dirpath), Path::new(&archive_filepath), options, share_code)
+        .and_then(|entries| Ok((entries, std::fs::metadata(&archive_filepath)?.len())));
 
-    original_file.write(original_text.as_bytes()).unwrap();
+    match archived {
+        Ok((entries, archive_size)) => Outcome::ArchiveCreated {
+            filepath: archive_filepath,
+            sizes: SizeComparison {
+                original: entries.iter().map(|entry| entry.len as usize).sum(),
+                compressed: archive_size as usize
+            }
+        },
+        Err(err) => failure(err)
+    }
+}
 
-    app.status = format!("Decompressed to {}", original_filepath)
+fn list_archive(filepath: String) -> Outcome {
+    match ArchiveReader::open(Path::new(&filepath)).and_then(ArchiveReader::entries) {
+        Ok(entries) => Outcome::ArchiveListed(ArchiveListing { filepath, entries }),
+        Err(err) => failure(err)
+    }
+}
+
+/// Extracts the whole archive, or only the file at `entry_path`, next to
+/// the archive.
+fn extract_archive(filepath: String, entry_path: Option<String>, restore_metadata: bool) -> Outcome {
+    let archive_path = Path::new(&filepath);
+    let destination = archive_path.parent().unwrap_or(Path::new("."));
+
+    let extracted = ArchiveReader::open(archive_path).and_then(|reader| match &entry_path {
+        Some(entry_path) => reader.extract_entry(entry_path, destination, restore_metadata),
+        None => reader.extract_all(destination, restore_metadata).map(|_| destination.to_path_buf())
+    });
+
+    match extracted {
+        Ok(path) => Outcome::Extracted(path.display().to_string()),
+        Err(err) => failure(err)
+    }
 }
 
 fn handle_filepath(app: &mut Application, filepath: String) {
-    let extension = std::path::Path::new(&filepath).extension().unwrap();
-    if extension == huffman::COMPRESSED_FILE_EXTENSION {
-        decompress_with_filepath(app, filepath)
+    app.archive = None;
+
+    if archive::is_archive(Path::new(&filepath)) {
+        app.start_job(format!("Reading archive {}", filepath),
+                      move || list_archive(filepath))
+    } else if Huffman::is_compressed(&filepath) {
+        let restore_metadata = app.keep_metadata;
+        app.start_job(format!("Decompressing {}", filepath),
+                      move || decompress_with_filepath(filepath, restore_metadata))
     } else {
-        compress_with_filepath(app, filepath)
+        let options = app.huffman_options();
+        if app.gzip {
+            app.start_job(format!("Compressing {} to gzip", filepath),
+                          move || gzip_with_filepath(filepath, options))
+        } else {
+            app.start_job(format!("Compressing {}", filepath),
+                          move || compress_with_filepath(filepath, options))
+        }
     }
 }
 
 impl eframe::App for Application {
-    fn update(mut self: &mut Self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_job(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading(&mut self.status);
+            if self.corrupted {
+                ui.heading(egui::RichText::new(&self.status).color(ui.visuals().error_fg_color));
+            } else {
+                ui.heading(&self.status);
+            }
 
             // ui.toggle_value(&mut self.toggle, "Whatev");
 
-            if ui.button("Open file").clicked() {
-                let filepath = tinyfiledialogs::open_file_dialog("File to compress", "", None);
-                match filepath {
-                    None => (),
-                    Some(filepath) => handle_filepath(&mut self, filepath)
+            ui.checkbox(&mut self.gzip, "Save files as gzip (.gz) instead");
+
+            ui.add_enabled_ui(!self.gzip, |ui| {
+                egui::ComboBox::from_label("Coder")
+                    .selected_text(self.coder.name())
+                    .show_ui(ui, |ui| {
+                        for coder in CODERS {
+                            if ui.selectable_label(coder.id() == self.coder.id(), coder.name()).clicked() {
+                                self.coder = *coder;
+                            }
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    for (method, label) in [(CodingMethod::Static, "Static blocks"), (CodingMethod::Adaptive, "Adaptive")] {
+                        ui.add_enabled_ui(self.coder.methods().contains(&method),
+                                          |ui| ui.radio_value(&mut self.method, method, label));
+                    }
+                });
+
+                ui.checkbox(&mut self.run_length, "Shorten runs of repeated symbols first");
+            });
+
+            ui.add_enabled_ui(self.limits_code_length(), |ui| ui.horizontal(|ui| {
+                ui.checkbox(&mut self.limit_code_length, "Limit code length to");
+                ui.add_enabled(self.limit_code_length,
+                               egui::Slider::new(&mut self.max_code_length, 1..=32).suffix(" bits"));
+            }));
+
+            ui.checkbox(&mut self.keep_metadata, "Keep file name, modification time and permissions");
+            ui.add_enabled(self.coder.uses_code_lengths(),
+                           egui::Checkbox::new(&mut self.share_code, "Share one code between the files of a folder"));
+
+            ui.add_enabled_ui(self.job.is_none(), |ui| ui.horizontal(|ui| {
+                if ui.button("Open file").clicked() {
+                    let filepath = tinyfiledialogs::open_file_dialog("File to compress", "", None);
+                    match filepath {
+                        None => (),
+                        Some(filepath) => handle_filepath(self, filepath)
+                    }
+                }
+
+                if ui.button("Compress folder").clicked() {
+                    if let Some(dirpath) = tinyfiledialogs::select_folder_dialog("Folder to compress", "") {
+                        let options = self.huffman_options();
+                        let share_code = self.share_code && options.coder.uses_code_lengths();
+                        self.archive = None;
+                        self.start_job(format!("Compressing folder {}", dirpath),
+                                       move || archive_directory(dirpath, options, share_code));
+                    }
+                }
+            }));
+
+            if let Some(sizes) = &self.size_comparison {
+                let ratio = 100.0 * sizes.compressed as f64 / sizes.original as f64;
+                ui.label(format!("{} bytes --> {} bytes ({:.1}%)",
+                                 sizes.original, sizes.compressed, ratio));
+            }
+
+            if self.coder_sizes.len() > 1 {
+                let sizes: Vec<_> = self.coder_sizes.iter()
+                    .map(|(name, size)| format!("{name}: {size} bytes"))
+                    .collect();
+                ui.label(sizes.join(", "));
+            }
+
+            if let Some(report) = &self.code_length_report {
+                ui.label(format!("Average code length of the blocks: {:.3} bits, longest {} bits",
+                                 report.average, report.max));
+                ui.label(format!("Unconstrained Huffman per block: {:.3} bits, longest {} bits",
+                                 report.unconstrained_average, report.unconstrained_max));
+            }
+
+            if let Some(listing) = &self.archive {
+                let mut extract = None;
+
+                ui.separator();
+                ui.add_enabled_ui(self.job.is_none(), |ui| {
+                    if ui.button("Extract all").clicked() {
+                        extract = Some(None);
+                    }
+
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        for entry in &listing.entries {
+                            ui.horizontal(|ui| match entry.kind {
+                                EntryKind::Directory => {
+                                    ui.label(format!("{}/", entry.path));
+                                },
+                                EntryKind::File => {
+                                    if ui.small_button("Extract").clicked() {
+                                        extract = Some(Some(entry.path.clone()));
+                                    }
+                                    ui.label(format!("{} ({} bytes --> {} bytes)",
+                                                     entry.path, entry.len, entry.compressed_len));
+                                }
+                            });
+                        }
+                    });
+                });
+
+                if let Some(entry_path) = extract {
+                    let (filepath, restore_metadata) = (listing.filepath.clone(), self.keep_metadata);
+                    self.start_job(format!("Extracting from {}", filepath),
+                                   move || extract_archive(filepath, entry_path, restore_metadata));
                 }
             }
         });
-        return;
     }
 
 }
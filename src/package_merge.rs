@@ -0,0 +1,163 @@
+/// Optimal prefix code lengths that never exceed `max_len` bits, computed
+/// with the package-merge algorithm (Larmore & Hirschberg, 1990).
+///
+/// `weights[i]` is the frequency of the i-th symbol and the result holds its
+/// code length at the same index. Every weight must be positive. If `max_len`
+/// is too small to give each symbol a distinct codeword it is raised to the
+/// smallest length that can.
+pub fn limited_code_lengths(weights: &[usize], max_len: u8) -> Vec<u8> {
+    let n = weights.len();
+    if n <= 1 {
+        return vec![1; n]
+    }
+
+    let min_len = usize::BITS - (n - 1).leading_zeros();
+    let max_len = (max_len as u32).max(min_len);
+
+    // Leaves sorted by weight, lightest first
+    let mut leaves: Vec<usize> = (0..n).collect();
+    leaves.sort_by_key(|i| weights[*i]);
+
+    // Each list holds the items of one level of the algorithm: either a
+    // leaf (`Some(symbol)`) or a package (`None`) of two items of the
+    // previous level. Packages are always made from the lightest items
+    // first, so the k-th package of a level is made of items 2k and 2k+1
+    // of the level before it.
+    let mut levels: Vec<Vec<Option<usize>>> = Vec::with_capacity(max_len as usize);
+    let mut prev_weights: Vec<usize> = Vec::new();
+
+    for _ in 0..max_len {
+        let packages: Vec<usize> = prev_weights.chunks_exact(2)
+            .map(|pair| pair[0] + pair[1])
+            .collect();
+
+        let mut items = Vec::with_capacity(n + packages.len());
+        let mut item_weights = Vec::with_capacity(n + packages.len());
+        let (mut l, mut p) = (0, 0);
+
+        while l < n || p < packages.len() {
+            let take_leaf = p == packages.len()
+                || (l < n && weights[leaves[l]] <= packages[p]);
+            if take_leaf {
+                items.push(Some(leaves[l]));
+                item_weights.push(weights[leaves[l]]);
+                l += 1;
+            } else {
+                items.push(None);
+                item_weights.push(packages[p]);
+                p += 1;
+            }
+        }
+
+        levels.push(items);
+        prev_weights = item_weights;
+    }
+
+    // The lightest 2n - 2 items of the last level form the solution. Every
+    // time a leaf is part of it, its code gets one bit longer.
+    let mut lengths = vec![0u8; n];
+    let mut selected = 2 * n - 2;
+
+    for items in levels.iter().rev() {
+        let mut packages = 0;
+        for item in &items[..selected] {
+            match item {
+                Some(symbol) => lengths[*symbol] += 1,
+                None => packages += 1
+            }
+        }
+        selected = 2 * packages;
+    }
+
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman::{code_lengths_for, Frequencies, HuffmanOptions};
+
+    /// Fibonacci weights, which give the deepest Huffman trees.
+    fn skewed(n: usize) -> Vec<usize> {
+        let mut weights = vec![1, 1];
+        while weights.len() < n {
+            weights.push(weights[weights.len() - 1] + weights[weights.len() - 2]);
+        }
+        weights
+    }
+
+    fn cost(weights: &[usize], lengths: &[u8]) -> usize {
+        weights.iter().zip(lengths).map(|(w, len)| w * *len as usize).sum()
+    }
+
+    /// The Kraft sum of `lengths`, in units of `2^-32`.
+    fn kraft_sum(lengths: &[u8]) -> u64 {
+        lengths.iter().map(|len| 1u64 << (32 - len)).sum()
+    }
+
+    fn unconstrained_lengths(weights: &[usize]) -> Vec<u8> {
+        let frequencies: Frequencies<u16> = (0..).zip(weights.iter().copied()).collect();
+        code_lengths_for(&frequencies, HuffmanOptions::default()).into_iter().map(|(_, len)| len).collect()
+    }
+
+    /// The cheapest lengths of at most `max_len` bits, by trying them all.
+    fn brute_force_cost(weights: &[usize], max_len: u8) -> usize {
+        let mut lengths = vec![1u8; weights.len()];
+        let mut best = usize::MAX;
+        loop {
+            if kraft_sum(&lengths) <= 1 << 32 {
+                best = best.min(cost(weights, &lengths));
+            }
+            let Some(i) = lengths.iter().position(|len| *len < max_len) else {
+                return best
+            };
+            lengths[..i].fill(1);
+            lengths[i] += 1;
+        }
+    }
+
+    #[test]
+    fn lengths_stay_within_the_limit() {
+        let weights = skewed(20);
+        assert!(unconstrained_lengths(&weights).iter().any(|len| *len > 10));
+
+        for max_len in 5..=12 {
+            let lengths = limited_code_lengths(&weights, max_len);
+            assert!(lengths.iter().all(|len| (1..=max_len).contains(len)), "{lengths:?} over {max_len} bits");
+        }
+
+        // Too low a limit for 20 symbols is raised to the 5 bits they need
+        assert!(limited_code_lengths(&weights, 2).iter().all(|len| *len <= 5));
+    }
+
+    #[test]
+    fn lengths_satisfy_kraft() {
+        for n in 2..=20 {
+            let weights = skewed(n);
+            for max_len in 5..=15 {
+                // Every codeword is needed, so the code is complete
+                assert_eq!(kraft_sum(&limited_code_lengths(&weights, max_len)), 1 << 32,
+                           "{n} symbols limited to {max_len} bits");
+            }
+        }
+    }
+
+    #[test]
+    fn lengths_are_optimal() {
+        let weights = skewed(20);
+        let unconstrained = unconstrained_lengths(&weights);
+        let unconstrained_max = *unconstrained.iter().max().unwrap();
+
+        // With room for the whole tree, nothing is lost
+        let lengths = limited_code_lengths(&weights, unconstrained_max);
+        assert_eq!(cost(&weights, &lengths), cost(&weights, &unconstrained));
+
+        // Limited, they cost more, and as little as any lengths can
+        let weights = skewed(7);
+        for max_len in 3..=6 {
+            let lengths = limited_code_lengths(&weights, max_len);
+            assert!(cost(&weights, &lengths) >= cost(&weights, &unconstrained_lengths(&weights)));
+            assert_eq!(cost(&weights, &lengths), brute_force_cost(&weights, max_len), "Limited to {max_len} bits");
+        }
+    }
+}
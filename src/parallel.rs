@@ -0,0 +1,34 @@
+//! Spreading independent pieces of work over scoped threads.
+
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// Number of threads to use when `requested` of them were asked for, 0
+/// meaning one per available core.
+pub fn threads(requested: usize) -> usize {
+    if requested > 0 {
+        return requested
+    }
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Applies `f` to every item, with the items split into contiguous runs
+/// over at most `threads` threads. The results are in the order of the
+/// items, however many threads were used.
+pub fn map<T: Sync, U: Send>(items: &[T], threads: usize, f: impl Fn(&T) -> U + Sync) -> Vec<U> {
+    if threads <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect()
+    }
+
+    let run_len = items.len().div_ceil(threads);
+    let f = &f;
+    thread::scope(|scope| {
+        let workers: Vec<_> = items.chunks(run_len)
+            .map(|run| scope.spawn(move || run.iter().map(f).collect::<Vec<_>>()))
+            .collect();
+
+        workers.into_iter()
+            .flat_map(|worker| worker.join().expect("A worker thread not to panic"))
+            .collect()
+    })
+}
@@ -0,0 +1,224 @@
+//! Static range coding, which unlike a Huffman code can spend a fraction
+//! of a bit on a symbol.
+//!
+//! The input is cut into blocks of `BLOCK_SYMBOLS` symbols, counted as for
+//! static Huffman blocks. The header of a block holds the exact count of
+//! every symbol in it, and the coded bytes follow.
+//!
+//! A block has at most `BLOCK_SYMBOLS` symbols, so the counts can be used
+//! as they are, without scaling them down. The coder itself is the one of
+//! LZMA: a 32 bit range shifted out a byte at a time, with carries
+//! propagated through the last byte that could still change.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::marker::PhantomData;
+
+use serde::{Serialize, Deserialize};
+
+use crate::block::{count, BlockCoder, BLOCK_SYMBOLS};
+use crate::huffman::Symbol;
+use crate::stream::{invalid_data, read_frame, SymbolDecoder};
+
+/// The range is shifted left whenever it falls under this.
+const TOP: u32 = 1 << 24;
+
+/// Bytes the encoder flushes at the end of a block.
+const FLUSH_BYTES: usize = 5;
+
+/// Largest payload a block can have: a symbol appearing once among
+/// `BLOCK_SYMBOLS` takes 16 bits, and a little more for rounding.
+const MAX_BLOCK_BYTES: usize = 3 * BLOCK_SYMBOLS + FLUSH_BYTES;
+
+#[derive(Serialize, Deserialize)]
+pub struct RangeBlockHeader {
+    /// Symbols in the block, as `to_u32` gives them and in increasing
+    /// order, along with how many times each appears
+    counts: Vec<(u32, u32)>,
+    byte_len: usize
+}
+
+struct RangeWriter {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    bytes: Vec<u8>
+}
+
+impl RangeWriter {
+    fn new() -> Self {
+        RangeWriter { low: 0, range: u32::MAX, cache: 0, cache_size: 1, bytes: Vec::new() }
+    }
+
+    /// Narrows the range to the `size` values from `start` out of `total`.
+    fn encode(&mut self, start: u32, size: u32, total: u32) {
+        let r = self.range / total;
+        self.low += (r * start) as u64;
+        self.range = r * size;
+
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    /// Writes out the top byte of `low`, unless a later carry could still
+    /// change it.
+    fn shift_low(&mut self) {
+        if self.low < 0xFF00_0000 || self.low > u32::MAX as u64 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            loop {
+                self.bytes.push(byte.wrapping_add(carry));
+                byte = 0xFF;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for _ in 0..FLUSH_BYTES {
+            self.shift_low();
+        }
+        self.bytes
+    }
+}
+
+struct RangeReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    code: u32,
+    range: u32,
+    r: u32
+}
+
+impl<'a> RangeReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        let mut reader = RangeReader { bytes, position: 0, code: 0, range: u32::MAX, r: 1 };
+        for _ in 0..FLUSH_BYTES {
+            reader.code = (reader.code << 8) | reader.next_byte() as u32;
+        }
+        reader
+    }
+
+    /// Past the end of the block, as many zeros as the flush left out.
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes.get(self.position).copied().unwrap_or(0);
+        self.position += 1;
+        byte
+    }
+
+    /// Where the next symbol falls among `total` values. Corrupted blocks
+    /// can point past them, they are kept to the last one.
+    fn value(&mut self, total: u32) -> u32 {
+        self.r = self.range / total;
+        (self.code / self.r).min(total - 1)
+    }
+
+    /// Narrows the range to that of the symbol `value` fell on.
+    fn consume(&mut self, start: u32, size: u32) {
+        self.code -= self.r * start;
+        self.range = self.r * size;
+
+        while self.range < TOP {
+            self.code = (self.code << 8) | self.next_byte() as u32;
+            self.range <<= 8;
+        }
+    }
+}
+
+/// Range codes each block with the counts of its symbols.
+pub struct RangeEncoder;
+
+impl<S: Symbol> BlockCoder<S> for RangeEncoder {
+    type Header = RangeBlockHeader;
+
+    const BLOCK_LEN: usize = BLOCK_SYMBOLS;
+
+    fn code_block(&self, symbols: &[S]) -> io::Result<(RangeBlockHeader, Vec<u8>)> {
+        let frequencies = count(symbols);
+        let total = symbols.len() as u32;
+
+        let mut ranges = HashMap::with_capacity(frequencies.len());
+        let mut start = 0;
+        for (c, freq) in &frequencies {
+            ranges.insert(*c, (start, *freq as u32));
+            start += *freq as u32;
+        }
+
+        let mut range_writer = RangeWriter::new();
+        for c in symbols {
+            let (start, size) = ranges[c];
+            range_writer.encode(start, size, total);
+        }
+        let bytes = range_writer.finish();
+
+        let header = RangeBlockHeader {
+            counts: frequencies.into_iter().map(|(c, freq)| (c.to_u32(), freq as u32)).collect(),
+            byte_len: bytes.len()
+        };
+        Ok((header, bytes))
+    }
+}
+
+pub struct RangeDecoder<S> {
+    symbols: PhantomData<S>
+}
+
+impl<S: Symbol> RangeDecoder<S> {
+    pub fn new() -> Self {
+        RangeDecoder { symbols: PhantomData }
+    }
+}
+
+impl<S: Symbol> SymbolDecoder for RangeDecoder<S> {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let Some(header): Option<RangeBlockHeader> = read_frame(&mut *reader)? else {
+            return Ok(false)
+        };
+        if header.byte_len > MAX_BLOCK_BYTES {
+            return Err(invalid_data(format!("Block of {} bytes is too long", header.byte_len)))
+        }
+        if !header.counts.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            return Err(invalid_data("Block symbols are not in order"))
+        }
+
+        let mut symbols = Vec::with_capacity(header.counts.len());
+        let mut starts = Vec::with_capacity(header.counts.len());
+        let mut total = 0u64;
+        for (n, freq) in &header.counts {
+            let c = S::from_u32(*n)
+                .ok_or_else(|| invalid_data(format!("Invalid symbol {n} in block")))?;
+            if *freq == 0 {
+                return Err(invalid_data("Symbol of a block appears no times"))
+            }
+            symbols.push(c);
+            starts.push(total as u32);
+            total += *freq as u64;
+        }
+        if total == 0 || total > BLOCK_SYMBOLS as u64 {
+            return Err(invalid_data(format!("Block of {total} symbols")))
+        }
+        let total = total as u32;
+
+        let mut bytes = vec![0; header.byte_len];
+        reader.read_exact(&mut bytes)?;
+
+        let mut range_reader = RangeReader::new(&bytes);
+        for _ in 0..total {
+            let value = range_reader.value(total);
+            let i = starts.partition_point(|start| *start <= value) - 1;
+            range_reader.consume(starts[i], header.counts[i].1);
+            symbols[i].push_to(out);
+        }
+
+        Ok(true)
+    }
+}
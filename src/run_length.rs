@@ -0,0 +1,260 @@
+//! Run-length coding ahead of any coder, for inputs with long runs of the
+//! same symbol.
+//!
+//! After four equal symbols in a row comes a count of how many more
+//! follow, from 0 to `MAX_EXTRA_RUN`, as the symbol of that number, so
+//! that counts are coded with the same code as everything else. This is
+//! the first stage of bzip2. A run shorter than four costs nothing more,
+//! a longer one as little as five symbols. The header flags streams coded
+//! this way (see `container`).
+
+use std::io::{self, Read, Write};
+
+use crate::huffman::Symbol;
+use crate::stream::{decode_utf8, invalid_data, SymbolDecoder, SymbolEncoder};
+
+/// Equal symbols in a row after which a count follows.
+const RUN_START: usize = 4;
+
+/// Most equal symbols a count adds, so that a run of them fits a byte.
+const MAX_EXTRA_RUN: usize = u8::MAX as usize - RUN_START;
+
+/// Codes runs of symbols before passing them on to `inner`.
+pub struct RunLengthEncoder<S> {
+    inner: Box<dyn SymbolEncoder<S>>,
+    last: Option<S>,
+    run: usize
+}
+
+impl<S: Symbol> RunLengthEncoder<S> {
+    pub fn new(inner: Box<dyn SymbolEncoder<S>>) -> Self {
+        RunLengthEncoder { inner, last: None, run: 0 }
+    }
+
+    fn end_run(&mut self, writer: &mut dyn Write) -> io::Result<()> {
+        let count = S::from_u32((self.run - RUN_START) as u32).expect("Run counts to be valid symbols");
+        self.inner.push(count, writer)?;
+        self.last = None;
+        self.run = 0;
+        Ok(())
+    }
+}
+
+impl<S: Symbol> SymbolEncoder<S> for RunLengthEncoder<S> {
+    fn push(&mut self, c: S, writer: &mut dyn Write) -> io::Result<()> {
+        if self.run >= RUN_START {
+            if self.last == Some(c) && self.run - RUN_START < MAX_EXTRA_RUN {
+                self.run += 1;
+                return Ok(())
+            }
+            self.end_run(writer)?;
+        }
+
+        self.inner.push(c, writer)?;
+        if self.last == Some(c) {
+            self.run += 1;
+        } else {
+            (self.last, self.run) = (Some(c), 1);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, writer: &mut dyn Write) -> io::Result<()> {
+        if self.run >= RUN_START {
+            self.end_run(writer)?;
+        }
+        self.inner.finish(writer)
+    }
+}
+
+/// Expands the runs in what `inner` decodes, symbols read as `S`.
+pub struct RunLengthDecoder<S> {
+    inner: Box<dyn SymbolDecoder>,
+    coded: Vec<u8>,
+    pending: Vec<u8>, // Bytes of a character split between two blocks
+    last: Option<S>,
+    run: usize
+}
+
+impl<S: Symbol> RunLengthDecoder<S> {
+    pub fn new(inner: Box<dyn SymbolDecoder>) -> Self {
+        RunLengthDecoder { inner, coded: Vec::new(), pending: Vec::new(), last: None, run: 0 }
+    }
+
+    /// Reads the next block from `inner` into `coded`.
+    fn next_coded(&mut self, reader: &mut dyn Read) -> io::Result<bool> {
+        self.coded.clear();
+        self.inner.decode_next(reader, &mut self.coded)
+    }
+
+    fn expand(&mut self, c: S, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.run == RUN_START {
+            let count = c.to_u32() as usize;
+            if count > MAX_EXTRA_RUN {
+                return Err(invalid_data(format!("Run of {} symbols is too long", count + RUN_START)))
+            }
+            let repeated = self.last.expect("A run to have a symbol");
+            for _ in 0..count {
+                repeated.push_to(out);
+            }
+            (self.last, self.run) = (None, 0);
+            return Ok(())
+        }
+
+        c.push_to(out);
+        if self.last == Some(c) {
+            self.run += 1;
+        } else {
+            (self.last, self.run) = (Some(c), 1);
+        }
+        Ok(())
+    }
+}
+
+impl SymbolDecoder for RunLengthDecoder<u8> {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        if !self.next_coded(reader)? {
+            return Ok(false)
+        }
+        let coded = std::mem::take(&mut self.coded);
+        for b in &coded {
+            self.expand(*b, out)?;
+        }
+        self.coded = coded;
+        Ok(true)
+    }
+}
+
+impl SymbolDecoder for RunLengthDecoder<char> {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        if !self.next_coded(reader)? {
+            if !self.pending.is_empty() {
+                return Err(invalid_data("Text ends in the middle of a character"))
+            }
+            return Ok(false)
+        }
+        let coded = std::mem::take(&mut self.coded);
+        let mut pending = std::mem::take(&mut self.pending);
+        decode_utf8(&mut pending, &coded, |c| self.expand(c, out))?;
+        (self.coded, self.pending) = (coded, pending);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes symbols as they come, one byte each, to see what the run
+    /// length coding passes on.
+    struct Raw;
+
+    impl SymbolEncoder<u8> for Raw {
+        fn push(&mut self, c: u8, writer: &mut dyn Write) -> io::Result<()> {
+            writer.write_all(&[c])
+        }
+
+        fn finish(self: Box<Self>, _writer: &mut dyn Write) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Writes characters as UTF-8, as the text coders decode them.
+    struct Utf8;
+
+    impl SymbolEncoder<char> for Utf8 {
+        fn push(&mut self, c: char, writer: &mut dyn Write) -> io::Result<()> {
+            writer.write_all(c.encode_utf8(&mut [0; 4]).as_bytes())
+        }
+
+        fn finish(self: Box<Self>, _writer: &mut dyn Write) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Hands out the given blocks as they are.
+    struct RawBlocks(Vec<Vec<u8>>);
+
+    impl SymbolDecoder for RawBlocks {
+        fn decode_next(&mut self, _reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+            if self.0.is_empty() {
+                return Ok(false)
+            }
+            out.extend(self.0.remove(0));
+            Ok(true)
+        }
+    }
+
+    fn shorten(bytes: &[u8]) -> Vec<u8> {
+        let mut coded = Vec::new();
+        let mut encoder = Box::new(RunLengthEncoder::new(Box::new(Raw)));
+        for b in bytes {
+            encoder.push(*b, &mut coded).unwrap();
+        }
+        encoder.finish(&mut coded).unwrap();
+        coded
+    }
+
+    fn expand<S: Symbol>(blocks: Vec<Vec<u8>>) -> io::Result<Vec<u8>> where RunLengthDecoder<S>: SymbolDecoder {
+        let mut decoder = RunLengthDecoder::<S>::new(Box::new(RawBlocks(blocks)));
+        let mut out = Vec::new();
+        while decoder.decode_next(&mut io::empty(), &mut out)? {}
+        Ok(out)
+    }
+
+    #[test]
+    fn runs_are_counted_after_four_symbols() {
+        assert_eq!(shorten(b"aaab"), b"aaab");
+        assert_eq!(shorten(b"aaaab"), b"aaaa\x00b");
+        assert_eq!(shorten(b"aaaaab"), b"aaaa\x01b");
+        // A count is a symbol of its own, even one equal to the run's
+        assert_eq!(shorten(&[1; 5]), [1, 1, 1, 1, 1]);
+
+        // The longest count, then what is left starts a run again
+        let run = vec![b'z'; RUN_START + MAX_EXTRA_RUN];
+        assert_eq!(shorten(&run), [b'z', b'z', b'z', b'z', MAX_EXTRA_RUN as u8]);
+        let mut longer = run.clone();
+        longer.extend([b'z'; 5]);
+        assert_eq!(shorten(&longer), [b'z', b'z', b'z', b'z', MAX_EXTRA_RUN as u8, b'z', b'z', b'z', b'z', 1]);
+    }
+
+    #[test]
+    fn runs_expand_back() {
+        let mut original = Vec::new();
+        for len in [1, 3, 4, 5, 8, 255, 256, 1000] {
+            original.extend(std::iter::repeat_n(len as u8, len));
+            original.push(b'.');
+        }
+        let coded = shorten(&original);
+        assert!(coded.len() < original.len() / 10);
+
+        // However the coded symbols are cut into blocks
+        let blocks = coded.chunks(7).map(<[u8]>::to_vec).collect();
+        assert_eq!(expand::<u8>(blocks).unwrap(), original);
+    }
+
+    #[test]
+    fn characters_split_between_blocks_expand() {
+        let original = "€€€€€€€€ 東京東京 😀😀😀😀😀".as_bytes();
+        let mut coded = Vec::new();
+        let mut encoder = Box::new(RunLengthEncoder::new(Box::new(Utf8)));
+        for c in std::str::from_utf8(original).unwrap().chars() {
+            encoder.push(c, &mut coded).unwrap();
+        }
+        encoder.finish(&mut coded).unwrap();
+
+        let blocks = coded.chunks(3).map(<[u8]>::to_vec).collect();
+        assert_eq!(expand::<char>(blocks).unwrap(), original);
+
+        // The first byte of a character, and nothing after it
+        coded.push(0xF0);
+        let err = expand::<char>(vec![coded]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn overlong_runs_are_rejected() {
+        let err = expand::<u8>(vec![vec![b'a', b'a', b'a', b'a', MAX_EXTRA_RUN as u8 + 1]]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
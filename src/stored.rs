@@ -0,0 +1,76 @@
+//! Streams holding the input as is, for when coding it would only make it
+//! bigger.
+//!
+//! The input is cut into chunks of at most `CHUNK_BYTES` bytes, each
+//! preceded by its length, and ends with an empty frame.
+
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+use crate::huffman::Symbol;
+use crate::stream::{invalid_data, read_frame, write_frame, SymbolDecoder, SymbolEncoder};
+
+pub const CHUNK_BYTES: usize = 1 << 16;
+
+/// Bytes of framing around `len` bytes of input: the length of every
+/// chunk and the end marker.
+pub fn framing_len(len: u64) -> u64 {
+    let chunk_len = postcard::to_allocvec(&Some(CHUNK_BYTES)).expect("A valid serialisation").len() as u64;
+    len.div_ceil(CHUNK_BYTES as u64) * chunk_len + 1
+}
+
+pub struct StoredEncoder<S> {
+    bytes: Vec<u8>,
+    symbols: PhantomData<S>
+}
+
+impl<S: Symbol> StoredEncoder<S> {
+    pub fn new() -> Self {
+        StoredEncoder {
+            bytes: Vec::with_capacity(CHUNK_BYTES + 4),
+            symbols: PhantomData
+        }
+    }
+
+    fn write_chunk(&mut self, len: usize, writer: &mut dyn Write) -> io::Result<()> {
+        write_frame(&Some(len), &mut *writer)?;
+        writer.write_all(&self.bytes[..len])?;
+        self.bytes.drain(..len);
+        Ok(())
+    }
+}
+
+impl<S: Symbol> SymbolEncoder<S> for StoredEncoder<S> {
+    fn push(&mut self, c: S, writer: &mut dyn Write) -> io::Result<()> {
+        c.push_to(&mut self.bytes);
+        if self.bytes.len() >= CHUNK_BYTES {
+            self.write_chunk(CHUNK_BYTES, writer)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, writer: &mut dyn Write) -> io::Result<()> {
+        if !self.bytes.is_empty() {
+            self.write_chunk(self.bytes.len(), writer)?;
+        }
+        write_frame(&None::<usize>, writer)
+    }
+}
+
+pub struct StoredDecoder;
+
+impl SymbolDecoder for StoredDecoder {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let Some(len): Option<usize> = read_frame(&mut *reader)? else {
+            return Ok(false)
+        };
+        if len > CHUNK_BYTES {
+            return Err(invalid_data(format!("Stored chunk of {len} bytes is too long")))
+        }
+
+        let start = out.len();
+        out.resize(start + len, 0);
+        reader.read_exact(&mut out[start..])?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,406 @@
+//! Compression and decompression over `Read` and `Write`, holding at most
+//! about one block of the stream in memory at a time.
+//!
+//! A compressed stream is a header saying how the symbols were read and
+//! coded (see `container`), followed by blocks (see `block`), adaptive
+//! chunks (see `adaptive`) or stored chunks (see `stored`), an end marker,
+//! and the CRC-32 of the original contents as 4 little endian bytes.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::block::{BlockDecoder, BlockEncoder};
+use crate::codec::{EntropyCoder, HuffmanCoder};
+use crate::container::{FileInfo, StreamHeader};
+use crate::crc32::Crc32;
+use crate::error::HuffmanError;
+use crate::run_length::{RunLengthDecoder, RunLengthEncoder};
+use crate::stored::{self, StoredDecoder, StoredEncoder};
+use crate::huffman::{CodeLengths, CodingMethod, HuffmanOptions, Symbol, SymbolMode};
+
+/// Length of the CRC-32 at the end of a stream.
+const CHECKSUM_BYTES: u64 = 4;
+
+/// Scratch space postcard needs to deserialise headers from a reader.
+const FRAME_SCRATCH_BYTES: usize = 1 << 12;
+
+pub fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub fn write_frame<T: Serialize>(value: &T, writer: impl Write) -> io::Result<()> {
+    postcard::to_io(value, writer)
+        .map(|_| ())
+        .map_err(io::Error::other)
+}
+
+/// Fails reads past the end of the input, which postcard would otherwise
+/// take for zero bytes, and a stream cut after a frame for its end marker.
+struct ExactReader<R>(R);
+
+impl<R: Read> Read for ExactReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read_exact(buf)?;
+        Ok(buf.len())
+    }
+}
+
+pub fn read_frame<T: DeserializeOwned>(reader: impl Read) -> io::Result<T> {
+    let mut scratch = [0; FRAME_SCRATCH_BYTES];
+    postcard::from_io((ExactReader(reader), &mut scratch))
+        .map(|(value, _)| value)
+        .map_err(|err| invalid_data(format!("Malformed compressed stream: {err}")))
+}
+
+/// The decompressed contents are not the ones that were compressed.
+#[derive(Debug, Clone, Copy)]
+pub struct ChecksumMismatch {
+    pub expected: u32,
+    pub actual: u32
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Checksum mismatch: the file is corrupted (expected CRC-32 {:08x}, got {:08x})",
+               self.expected, self.actual)
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Feeds the characters in `pending` followed by `bytes` to `f`. An
+/// incomplete character at the end is left in `pending`, to be finished by
+/// the next call.
+pub fn decode_utf8(pending: &mut Vec<u8>, bytes: &[u8],
+                   mut f: impl FnMut(char) -> io::Result<()>) -> io::Result<()> {
+    let joined;
+    let data = if pending.is_empty() {
+        bytes
+    } else {
+        joined = [std::mem::take(pending).as_slice(), bytes].concat();
+        joined.as_slice()
+    };
+
+    let valid = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) => {
+            if err.error_len().is_some() {
+                return Err(invalid_data("Text is not valid UTF-8"))
+            }
+            let (valid, incomplete) = data.split_at(err.valid_up_to());
+            pending.extend_from_slice(incomplete);
+            std::str::from_utf8(valid).unwrap()
+        }
+    };
+
+    for c in valid.chars() {
+        f(c)?;
+    }
+    Ok(())
+}
+
+/// Codes symbols one at a time, writing the compressed stream as it goes.
+pub trait SymbolEncoder<S> {
+    fn push(&mut self, c: S, writer: &mut dyn Write) -> io::Result<()>;
+
+    /// Writes whatever is left and the end marker.
+    fn finish(self: Box<Self>, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Decodes a compressed stream one block or chunk at a time.
+pub trait SymbolDecoder {
+    /// Appends the next part of the original contents to `out`, returning
+    /// `false` at the end of the stream.
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool>;
+}
+
+/// Whether a stream coded with `options` shortens runs first.
+fn codes_runs(options: HuffmanOptions) -> bool {
+    options.run_length && options.method != CodingMethod::Stored
+}
+
+/// The encoder of `options.coder` for input read as `mode`, along with
+/// the mode it actually codes: text is coded as bytes by coders that only
+/// code bytes.
+fn encoding(mode: SymbolMode, options: HuffmanOptions) -> io::Result<(SymbolMode, Encoding)> {
+    let coder = options.coder;
+    if options.method == CodingMethod::Stored {
+        return Ok(match mode {
+            SymbolMode::Text => (mode, Encoding::Text(Box::new(StoredEncoder::new()))),
+            SymbolMode::Binary => (mode, Encoding::Binary(Box::new(StoredEncoder::new())))
+        })
+    }
+    if !coder.methods().contains(&options.method) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("The {} coder has no {:?} method", coder.name(), options.method)))
+    }
+
+    let byte_encoder = || shorten_runs(coder.byte_encoder(options), options);
+    match mode {
+        SymbolMode::Text => match coder.text_encoder(options) {
+            Some(encoder) => Ok((SymbolMode::Text, Encoding::Text(shorten_runs(encoder, options)))),
+            None => Ok((SymbolMode::Binary, Encoding::Binary(byte_encoder())))
+        },
+        SymbolMode::Binary => Ok((SymbolMode::Binary, Encoding::Binary(byte_encoder())))
+    }
+}
+
+/// `encoder`, shortening runs beforehand if `options` say so.
+fn shorten_runs<S: Symbol>(encoder: Box<dyn SymbolEncoder<S>>,
+                           options: HuffmanOptions) -> Box<dyn SymbolEncoder<S>> {
+    if codes_runs(options) {
+        Box::new(RunLengthEncoder::new(encoder))
+    } else {
+        encoder
+    }
+}
+
+fn decoder(header: &StreamHeader) -> io::Result<Box<dyn SymbolDecoder>> {
+    if header.method == CodingMethod::Stored {
+        return Ok(Box::new(StoredDecoder))
+    }
+
+    let decoder = header.coder.decoder(header.mode, header.method)
+        .ok_or_else(|| invalid_data(format!("The {} coder has no {:?} method for {:?} input",
+                                            header.coder.name(), header.method, header.mode)))?;
+    Ok(expand_runs(header, decoder))
+}
+
+/// `decoder`, expanding runs afterwards if the header says so.
+fn expand_runs(header: &StreamHeader, decoder: Box<dyn SymbolDecoder>) -> Box<dyn SymbolDecoder> {
+    match (header.run_length, header.mode) {
+        (false, _) => decoder,
+        (true, SymbolMode::Text) => Box::new(RunLengthDecoder::<char>::new(decoder)),
+        (true, SymbolMode::Binary) => Box::new(RunLengthDecoder::<u8>::new(decoder))
+    }
+}
+
+/// Size of a stream with `original_len` bytes stored as is, the most that
+/// compressing them with `Huffman::compress` can take. `info` is what the
+/// header records, which counts too. Short inputs with nothing recorded
+/// grow by 15 bytes at most: 8 of header, 3 of framing and the CRC.
+pub fn max_compressed_len(original_len: u64, info: &FileInfo) -> u64 {
+    let header = StreamHeader {
+        coder: &HuffmanCoder,
+        mode: SymbolMode::Binary,
+        method: CodingMethod::Stored,
+        run_length: false,
+        info: info.clone()
+    };
+    let mut header_bytes = Vec::new();
+    header.write_to(&mut header_bytes).expect("Writing to memory not to fail");
+
+    header_bytes.len() as u64 + original_len + stored::framing_len(original_len) + CHECKSUM_BYTES
+}
+
+enum Encoding {
+    Text(Box<dyn SymbolEncoder<char>>),
+    Binary(Box<dyn SymbolEncoder<u8>>)
+}
+
+/// Compresses everything written to it into `W`, one block at a time.
+/// Call `finish` once all the input has been written.
+pub struct HuffmanEncoder<W: Write> {
+    writer: W,
+    encoding: Encoding,
+    pending: Vec<u8>, // Bytes of a character split between two writes
+    original_len: Option<u64>,
+    written: u64,
+    crc: Crc32
+}
+
+impl<W: Write> HuffmanEncoder<W> {
+    /// Starts a stream whose input is read as `mode` says. Input that is
+    /// not known to be UTF-8 text can always be compressed as binary.
+    /// `info` is recorded in the header, and its length checked by `finish`.
+    pub fn new(writer: W, mode: SymbolMode, info: FileInfo,
+               options: HuffmanOptions) -> io::Result<Self> {
+        let (mode, encoding) = encoding(mode, options)?;
+        let header = StreamHeader {
+            coder: options.coder,
+            mode,
+            method: options.method,
+            run_length: codes_runs(options),
+            info
+        };
+
+        Self::with_encoding(writer, header, encoding)
+    }
+
+    /// Starts a stream of bytes in static blocks that reuse `shared`
+    /// whenever that is cheaper than a code of their own. It can only be
+    /// decoded given the same code, with `HuffmanDecoder::with_shared_code`.
+    pub fn with_shared_code(writer: W, info: FileInfo, options: HuffmanOptions,
+                            shared: &CodeLengths<u8>) -> io::Result<Self> {
+        let header = StreamHeader {
+            coder: &HuffmanCoder,
+            mode: SymbolMode::Binary,
+            method: CodingMethod::Static,
+            run_length: false,
+            info
+        };
+        let encoding = Encoding::Binary(Box::new(BlockEncoder::with_code(options, shared.clone())));
+
+        Self::with_encoding(writer, header, encoding)
+    }
+
+    fn with_encoding(mut writer: W, header: StreamHeader, encoding: Encoding) -> io::Result<Self> {
+        let original_len = header.info.len;
+        header.write_to(&mut writer)?;
+
+        Ok(HuffmanEncoder {
+            writer,
+            encoding,
+            pending: Vec::new(),
+            original_len,
+            written: 0,
+            crc: Crc32::new()
+        })
+    }
+
+    /// Writes the last block, the end of the stream and the checksum.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            return Err(invalid_data("Text ends in the middle of a character"))
+        }
+        if self.original_len.is_some_and(|len| len != self.written) {
+            return Err(invalid_data("Input length differs from the one in the header"))
+        }
+
+        match self.encoding {
+            Encoding::Text(encoder) => encoder.finish(&mut self.writer)?,
+            Encoding::Binary(encoder) => encoder.finish(&mut self.writer)?
+        }
+        self.writer.write_all(&self.crc.value().to_le_bytes())?;
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for HuffmanEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let HuffmanEncoder { writer, encoding, pending, .. } = self;
+
+        match encoding {
+            Encoding::Text(encoder) =>
+                decode_utf8(pending, buf, |c| encoder.push(c, writer))?,
+            Encoding::Binary(encoder) =>
+                for b in buf {
+                    encoder.push(*b, writer)?;
+                }
+        }
+
+        self.written += buf.len() as u64;
+        self.crc.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads the original contents back out of a compressed stream, one block
+/// at a time. Text is produced UTF-8 encoded.
+pub struct HuffmanDecoder<R: Read> {
+    reader: R,
+    decoder: Box<dyn SymbolDecoder>,
+    decoded: Vec<u8>, // Contents of the current block
+    position: usize,
+    finished: bool,
+    info: FileInfo,
+    decoded_len: u64,
+    crc: Crc32
+}
+
+impl<R: Read> HuffmanDecoder<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let header = StreamHeader::read_from(&mut reader)?;
+
+        let decoder = decoder(&header)?;
+        Ok(Self::with_decoder(reader, header, decoder))
+    }
+
+    /// Reads a stream written by `HuffmanEncoder::with_shared_code`, or a
+    /// stored one.
+    pub fn with_shared_code(mut reader: R, shared: &CodeLengths<u8>) -> io::Result<Self> {
+        let header = StreamHeader::read_from(&mut reader)?;
+
+        let huffman = header.coder.id() == HuffmanCoder.id();
+        let decoder: Box<dyn SymbolDecoder> = match (header.mode, header.method) {
+            (SymbolMode::Binary, CodingMethod::Static) if huffman =>
+                expand_runs(&header, Box::new(BlockDecoder::with_code(0, shared)?)),
+            (_, CodingMethod::Stored) => Box::new(StoredDecoder),
+            _ => return Err(invalid_data("Stream was not coded with a shared code"))
+        };
+        Ok(Self::with_decoder(reader, header, decoder))
+    }
+
+    fn with_decoder(reader: R, header: StreamHeader, decoder: Box<dyn SymbolDecoder>) -> Self {
+        HuffmanDecoder {
+            reader,
+            decoder,
+            decoded: Vec::new(),
+            position: 0,
+            finished: false,
+            info: header.info,
+            decoded_len: 0,
+            crc: Crc32::new()
+        }
+    }
+
+    /// What the header records about the original file.
+    pub fn info(&self) -> &FileInfo {
+        &self.info
+    }
+
+    fn next_block(&mut self) -> io::Result<()> {
+        self.decoded.clear();
+        self.position = 0;
+
+        if self.decoder.decode_next(&mut self.reader, &mut self.decoded)? {
+            self.decoded_len += self.decoded.len() as u64;
+            self.crc.update(&self.decoded);
+            return Ok(())
+        }
+
+        self.finished = true;
+
+        let mut expected = [0; 4];
+        self.reader.read_exact(&mut expected)?;
+        let mismatch = ChecksumMismatch {
+            expected: u32::from_le_bytes(expected),
+            actual: self.crc.value()
+        };
+        if mismatch.expected != mismatch.actual {
+            return Err(HuffmanError::Corrupted(mismatch).into_io())
+        }
+
+        if self.info.len.is_some_and(|len| len != self.decoded_len) {
+            return Err(invalid_data("Decompressed length differs from the one in the header"))
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for HuffmanDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decoded.len() {
+            if self.finished {
+                return Ok(0)
+            }
+            self.next_block()?;
+        }
+
+        let remaining = &self.decoded[self.position..];
+        let n = remaining.len().min(buf.len());
+        buf[..n].copy_from_slice(&remaining[..n]);
+        self.position += n;
+
+        Ok(n)
+    }
+}
@@ -0,0 +1,236 @@
+//! Table-based asymmetric numeral systems (tANS), the coder of FSE and
+//! zstd: close to a range coder in size, with a single table lookup per
+//! symbol to decode.
+//!
+//! The input is cut into blocks of `BLOCK_SYMBOLS` symbols, counted as for
+//! static Huffman blocks. The counts are scaled to add up to the size of
+//! the table, a power of two, and the table spreads every symbol over as
+//! many states as its scaled count. The header of a block holds the
+//! scaled counts, and the coded bits follow.
+//!
+//! Symbols are coded last to first, so that they decode first to last.
+//! The bits start with the final state of the encoder, and then hold the
+//! bits of every symbol in the order they are decoded.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::marker::PhantomData;
+
+use serde::{Serialize, Deserialize};
+
+use crate::bits::{BitReader, BitWriter};
+use crate::block::{count, BlockCoder, BLOCK_SYMBOLS};
+use crate::huffman::{Frequencies, Symbol};
+use crate::stream::{invalid_data, read_frame, SymbolDecoder};
+
+/// Smallest table, for blocks with few distinct symbols.
+const MIN_TABLE_LOG: u8 = 11;
+
+/// Largest table, which holds a block with every symbol distinct.
+const MAX_TABLE_LOG: u8 = BLOCK_SYMBOLS.ilog2() as u8;
+
+/// Largest payload a block can have, every symbol taking all the bits of
+/// a state.
+const MAX_BLOCK_BYTES: usize = (BLOCK_SYMBOLS + 1) * MAX_TABLE_LOG as usize / 8 + 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct TansBlockHeader {
+    table_log: u8,
+    /// Symbols in the block, as `to_u32` gives them and in increasing
+    /// order, along with their counts scaled to add up to the table size
+    counts: Vec<(u32, u32)>,
+    symbol_count: usize,
+    bit_count: usize
+}
+
+/// Scales `frequencies` to add up to `1 << table_log`, keeping every
+/// symbol at least 1.
+fn normalize<S: Symbol>(frequencies: &Frequencies<S>, table_log: u8) -> Vec<u32> {
+    let table_size = 1u64 << table_log;
+    let total: u64 = frequencies.values().map(|freq| *freq as u64).sum();
+
+    let mut counts: Vec<u32> = frequencies.values()
+        .map(|freq| ((*freq as u64 * table_size + total / 2) / total).max(1) as u32)
+        .collect();
+
+    // Rounding leaves the sum a little off, the most frequent symbols make
+    // up for it
+    let mut by_count: Vec<usize> = (0..counts.len()).collect();
+    by_count.sort_by_key(|i| std::cmp::Reverse(counts[*i]));
+
+    let sum: u64 = counts.iter().map(|n| *n as u64).sum();
+    if sum < table_size {
+        counts[by_count[0]] += (table_size - sum) as u32;
+    }
+    let mut excess = sum.saturating_sub(table_size);
+    while excess > 0 {
+        for i in &by_count {
+            if excess == 0 {
+                break
+            }
+            if counts[*i] > 1 {
+                counts[*i] -= 1;
+                excess -= 1;
+            }
+        }
+    }
+
+    counts
+}
+
+/// The symbol, by its index among those of the block, of every state.
+fn spread(counts: &[u32], table_log: u8) -> Vec<u32> {
+    let table_size = 1usize << table_log;
+    let mask = table_size - 1;
+    // Odd, so that it goes through every state before coming back
+    let step = (table_size >> 1) + (table_size >> 3) + 3;
+
+    let mut symbols = vec![0; table_size];
+    let mut position = 0;
+    for (i, n) in counts.iter().enumerate() {
+        for _ in 0..*n {
+            symbols[position] = i as u32;
+            position = (position + step) & mask;
+        }
+    }
+    symbols
+}
+
+fn table_log_for(distinct: usize) -> u8 {
+    (distinct.next_power_of_two().ilog2() as u8).clamp(MIN_TABLE_LOG, MAX_TABLE_LOG)
+}
+
+/// Codes each block with a table spread from the scaled counts of its
+/// symbols.
+pub struct TansEncoder;
+
+impl<S: Symbol> BlockCoder<S> for TansEncoder {
+    type Header = TansBlockHeader;
+
+    const BLOCK_LEN: usize = BLOCK_SYMBOLS;
+
+    fn code_block(&self, symbols: &[S]) -> io::Result<(TansBlockHeader, Vec<u8>)> {
+        let frequencies = count(symbols);
+        let table_log = table_log_for(frequencies.len());
+        let table_size = 1u32 << table_log;
+        let counts = normalize(&frequencies, table_log);
+
+        // The states each symbol goes to, by what is left of the state
+        // after shifting out its bits
+        let mut next_states: Vec<Vec<u32>> = counts.iter().map(|n| Vec::with_capacity(*n as usize)).collect();
+        for (u, i) in spread(&counts, table_log).into_iter().enumerate() {
+            next_states[i as usize].push(table_size + u as u32);
+        }
+
+        let indices: HashMap<S, usize> = frequencies.keys().enumerate().map(|(i, c)| (*c, i)).collect();
+
+        let mut chunks = Vec::with_capacity(symbols.len());
+        let mut state = table_size;
+        for c in symbols.iter().rev() {
+            let i = indices[c];
+            let n = counts[i];
+
+            let mut bits = state.ilog2() - n.ilog2();
+            if state >> bits < n {
+                bits -= 1;
+            }
+            chunks.push((state & ((1 << bits) - 1), bits));
+            state = next_states[i][((state >> bits) - n) as usize];
+        }
+
+        let mut bit_writer = BitWriter::new();
+        bit_writer.write((state - table_size) as u64, table_log as u32);
+        for (value, bits) in chunks.into_iter().rev() {
+            bit_writer.write(value as u64, bits);
+        }
+        let (bytes, bit_count) = bit_writer.finish();
+
+        let header = TansBlockHeader {
+            table_log,
+            counts: frequencies.keys().zip(counts).map(|(c, n)| (c.to_u32(), n)).collect(),
+            symbol_count: symbols.len(),
+            bit_count
+        };
+        Ok((header, bytes))
+    }
+}
+
+/// What decoding a state gives: a symbol, by its index, and how to get
+/// the next state from the bits that follow.
+#[derive(Clone, Copy)]
+struct DecodeEntry {
+    symbol: u32,
+    bits: u32,
+    base: u32
+}
+
+pub struct TansDecoder<S> {
+    symbols: PhantomData<S>
+}
+
+impl<S: Symbol> TansDecoder<S> {
+    pub fn new() -> Self {
+        TansDecoder { symbols: PhantomData }
+    }
+}
+
+impl<S: Symbol> SymbolDecoder for TansDecoder<S> {
+    fn decode_next(&mut self, reader: &mut dyn Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let Some(header): Option<TansBlockHeader> = read_frame(&mut *reader)? else {
+            return Ok(false)
+        };
+        let TansBlockHeader { table_log, counts, symbol_count, bit_count } = header;
+
+        if !(MIN_TABLE_LOG..=MAX_TABLE_LOG).contains(&table_log) {
+            return Err(invalid_data(format!("Invalid table size 2^{table_log}")))
+        }
+        if symbol_count == 0 || symbol_count > BLOCK_SYMBOLS {
+            return Err(invalid_data(format!("Block of {symbol_count} symbols")))
+        }
+        let len = bit_count.div_ceil(8);
+        if len > MAX_BLOCK_BYTES {
+            return Err(invalid_data(format!("Block of {len} bytes is too long")))
+        }
+        if !counts.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            return Err(invalid_data("Block symbols are not in order"))
+        }
+
+        let table_size = 1u32 << table_log;
+        let symbols = counts.iter()
+            .map(|(n, _)| S::from_u32(*n).ok_or_else(|| invalid_data(format!("Invalid symbol {n} in block"))))
+            .collect::<io::Result<Vec<S>>>()?;
+        let counts: Vec<u32> = counts.into_iter().map(|(_, n)| n).collect();
+        if counts.contains(&0) || counts.iter().map(|n| *n as u64).sum::<u64>() != table_size as u64 {
+            return Err(invalid_data("Block counts do not add up to the table size"))
+        }
+
+        let mut next = counts.clone();
+        let table: Vec<DecodeEntry> = spread(&counts, table_log).into_iter()
+            .map(|i| {
+                let x = next[i as usize];
+                next[i as usize] += 1;
+                let bits = table_log as u32 - x.ilog2();
+                DecodeEntry { symbol: i, bits, base: (x << bits) - table_size }
+            })
+            .collect();
+
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+
+        let mut bits = BitReader::new(&bytes);
+        let mut state = bits.peek(table_log as u32) as u32;
+        bits.consume(table_log as u32);
+        for _ in 0..symbol_count {
+            let entry = table[state as usize];
+            symbols[entry.symbol as usize].push_to(out);
+            state = entry.base + bits.peek(entry.bits) as u32;
+            bits.consume(entry.bits);
+        }
+
+        if bits.position() != bit_count {
+            return Err(invalid_data("Block does not decode to its length"))
+        }
+
+        Ok(true)
+    }
+}
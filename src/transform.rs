@@ -0,0 +1,322 @@
+//! Reversible transforms of blocks of symbols, which make them cheaper to
+//! code without coding them themselves.
+//!
+//! Each transform is a `Stage`, and stages chain into a `Pipeline`, itself
+//! a stage. The block sorting of bzip2, for instance, is
+//!
+//! ```
+//! use rust_huffman_egui::transform::{Bwt, MoveToFront, Pipeline, Stage, ZeroRunLength};
+//!
+//! let pipeline = Pipeline::new().then(Bwt).then(MoveToFront).then(ZeroRunLength);
+//! let block: Vec<u16> = b"abracadabra".iter().map(|b| *b as u16).collect();
+//! let transformed = pipeline.forward(block.clone())?;
+//! assert_eq!(pipeline.inverse(transformed, block.len() + 4)?, block);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Symbols are `u16`, so that a stage can widen the alphabet of the one
+//! before it, as zero run-length coding does.
+
+use std::fmt::Debug;
+use std::io;
+
+use crate::stream::invalid_data;
+
+/// A transform of a block, undone by `inverse`.
+pub trait Stage: Debug + Send + Sync {
+    fn forward(&self, block: Vec<u16>) -> io::Result<Vec<u16>>;
+
+    /// Undoes `forward`. The input is taken for corrupted should it give
+    /// back more than `max_len` symbols.
+    fn inverse(&self, block: Vec<u16>, max_len: usize) -> io::Result<Vec<u16>>;
+}
+
+/// Stages applied one after the other, and undone in reverse.
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Adds `stage` after the others.
+    pub fn then(mut self, stage: impl Stage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+}
+
+impl Stage for Pipeline {
+    fn forward(&self, block: Vec<u16>) -> io::Result<Vec<u16>> {
+        self.stages.iter().try_fold(block, |block, stage| stage.forward(block))
+    }
+
+    fn inverse(&self, block: Vec<u16>, max_len: usize) -> io::Result<Vec<u16>> {
+        self.stages.iter().rev().try_fold(block, |block, stage| stage.inverse(block, max_len))
+    }
+}
+
+/// The Burrows-Wheeler transform: the symbol before each suffix of the
+/// block, with suffixes in sorted order. Symbols that come before similar
+/// contexts end up together.
+///
+/// The block is sorted as if it ended with a symbol smaller than any
+/// other, whose row is appended as four symbols, the bytes of it little
+/// endian.
+#[derive(Debug)]
+pub struct Bwt;
+
+/// Bytes the row of the end of the block takes.
+const PRIMARY_INDEX_SYMBOLS: usize = 4;
+
+/// Positions of the suffixes of `block` in sorted order, counting the
+/// empty suffix, by prefix doubling: suffixes sorted by their first `k`
+/// symbols fall in groups, whose members the ranks of the `k` symbols
+/// after them then sort by their first `2k`. Groups of one are done with.
+fn suffix_array(block: &[u16]) -> Vec<u32> {
+    let n = block.len() + 1;
+    // The end of the block sorts first
+    let first = |i: usize| block.get(i).map_or(0, |c| *c as u32 + 1);
+    let mut suffixes: Vec<u32> = (0..n as u32).collect();
+    suffixes.sort_unstable_by_key(|i| first(*i as usize));
+
+    // The rank of a suffix is where its group starts
+    let mut rank = vec![0u32; n];
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for end in 1..=n {
+        if end == n || first(suffixes[end] as usize) != first(suffixes[start] as usize) {
+            for i in &suffixes[start..end] {
+                rank[*i as usize] = start as u32;
+            }
+            if end - start > 1 {
+                groups.push(start..end);
+            }
+            start = end;
+        }
+    }
+
+    let mut k = 1;
+    let mut keyed: Vec<(u32, u32)> = Vec::new();
+    let mut new_ranks: Vec<(u32, u32)> = Vec::new();
+    while !groups.is_empty() {
+        // Ranks only change once every group is sorted, as the keys of
+        // one group are the ranks of others
+        new_ranks.clear();
+        let mut new_groups = Vec::new();
+        for group in groups {
+            keyed.clear();
+            keyed.extend(suffixes[group.clone()].iter()
+                .map(|i| (rank.get(*i as usize + k).map_or(0, |r| *r + 1), *i)));
+            keyed.sort_unstable();
+
+            let mut start = 0;
+            for end in 1..=keyed.len() {
+                if end == keyed.len() || keyed[end].0 != keyed[start].0 {
+                    let group_start = group.start + start;
+                    for (_, i) in &keyed[start..end] {
+                        new_ranks.push((*i, group_start as u32));
+                    }
+                    if end - start > 1 {
+                        new_groups.push(group_start..group.start + end);
+                    }
+                    start = end;
+                }
+            }
+            for (position, (_, i)) in group.zip(&keyed) {
+                suffixes[position] = *i;
+            }
+        }
+
+        for (i, r) in &new_ranks {
+            rank[*i as usize] = *r;
+        }
+        groups = new_groups;
+        k *= 2;
+    }
+
+    suffixes
+}
+
+impl Stage for Bwt {
+    fn forward(&self, block: Vec<u16>) -> io::Result<Vec<u16>> {
+        if block.len() >= u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Block is too long to sort"))
+        }
+
+        let mut transformed = Vec::with_capacity(block.len() + PRIMARY_INDEX_SYMBOLS);
+        let mut primary_index = 0u32;
+        for (row, suffix) in suffix_array(&block).into_iter().enumerate() {
+            match suffix {
+                0 => primary_index = row as u32,
+                _ => transformed.push(block[suffix as usize - 1])
+            }
+        }
+
+        transformed.extend(primary_index.to_le_bytes().map(u16::from));
+        Ok(transformed)
+    }
+
+    fn inverse(&self, mut block: Vec<u16>, max_len: usize) -> io::Result<Vec<u16>> {
+        if block.len() < PRIMARY_INDEX_SYMBOLS || block.len() > max_len {
+            return Err(invalid_data(format!("Sorted block of {} symbols", block.len())))
+        }
+        let index_symbols = block.split_off(block.len() - PRIMARY_INDEX_SYMBOLS);
+        let index_bytes = index_symbols.iter()
+            .map(|c| u8::try_from(*c).map_err(|_| invalid_data("Invalid index of sorted block")))
+            .collect::<io::Result<Vec<u8>>>()?;
+        let primary_index = u32::from_le_bytes([index_bytes[0], index_bytes[1], index_bytes[2], index_bytes[3]]) as usize;
+        if primary_index > block.len() {
+            return Err(invalid_data("Invalid index of sorted block"))
+        }
+
+        // The last column in full, with the end of the block as 0 and
+        // every symbol one more
+        let last: Vec<u32> = block[..primary_index].iter().map(|c| *c as u32 + 1)
+            .chain([0])
+            .chain(block[primary_index..].iter().map(|c| *c as u32 + 1))
+            .collect();
+
+        // Rows of the first column each symbol starts at
+        let mut starts = vec![0u32; u16::MAX as usize + 2];
+        for c in &last {
+            starts[*c as usize] += 1;
+        }
+        let mut total = 0;
+        for start in starts.iter_mut() {
+            (*start, total) = (total, total + *start);
+        }
+
+        // The row of the suffix one symbol longer than that of each row
+        let mut previous = vec![0u32; last.len()];
+        for (row, c) in last.iter().enumerate() {
+            previous[row] = starts[*c as usize];
+            starts[*c as usize] += 1;
+        }
+
+        let mut original = vec![0; block.len()];
+        let mut row = 0;
+        for c in original.iter_mut().rev() {
+            *c = last[row].checked_sub(1)
+                .ok_or_else(|| invalid_data("Sorted block ends too early"))? as u16;
+            row = previous[row] as usize;
+        }
+        Ok(original)
+    }
+}
+
+/// Replaces every symbol with how many distinct symbols were seen since it
+/// was last seen, which turns the runs of similar symbols left by the BWT
+/// into mostly small numbers.
+#[derive(Debug)]
+pub struct MoveToFront;
+
+fn initial_order() -> Vec<u16> {
+    (0..=u16::MAX).collect()
+}
+
+impl Stage for MoveToFront {
+    fn forward(&self, mut block: Vec<u16>) -> io::Result<Vec<u16>> {
+        let mut order = initial_order();
+        for c in block.iter_mut() {
+            let position = order.iter().position(|d| d == c).expect("Every symbol in the order");
+            order.copy_within(0..position, 1);
+            order[0] = *c;
+            *c = position as u16;
+        }
+        Ok(block)
+    }
+
+    fn inverse(&self, mut block: Vec<u16>, max_len: usize) -> io::Result<Vec<u16>> {
+        if block.len() > max_len {
+            return Err(invalid_data(format!("Block of {} symbols is too long", block.len())))
+        }
+
+        let mut order = initial_order();
+        for c in block.iter_mut() {
+            let position = *c as usize;
+            let d = order[position];
+            order.copy_within(0..position, 1);
+            order[0] = d;
+            *c = d;
+        }
+        Ok(block)
+    }
+}
+
+/// Codes runs of zeros as their length written in bijective base 2, with
+/// `RUN_A` for a digit 1 and `RUN_B` for a digit 2, as bzip2 does. Other
+/// symbols move one up to make room, so they must be below `u16::MAX`.
+#[derive(Debug)]
+pub struct ZeroRunLength;
+
+pub const RUN_A: u16 = 0;
+pub const RUN_B: u16 = 1;
+
+impl Stage for ZeroRunLength {
+    fn forward(&self, block: Vec<u16>) -> io::Result<Vec<u16>> {
+        let mut coded = Vec::with_capacity(block.len());
+        let mut run = 0usize;
+
+        let end_run = |coded: &mut Vec<u16>, mut run: usize| {
+            while run > 0 {
+                if run % 2 == 1 {
+                    coded.push(RUN_A);
+                    run = (run - 1) / 2;
+                } else {
+                    coded.push(RUN_B);
+                    run = (run - 2) / 2;
+                }
+            }
+        };
+
+        for c in block {
+            if c == 0 {
+                run += 1;
+                continue
+            }
+            end_run(&mut coded, run);
+            run = 0;
+
+            let c = c.checked_add(1)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Symbol too large for zero runs"))?;
+            coded.push(c);
+        }
+        end_run(&mut coded, run);
+
+        Ok(coded)
+    }
+
+    fn inverse(&self, block: Vec<u16>, max_len: usize) -> io::Result<Vec<u16>> {
+        let mut decoded = Vec::with_capacity(block.len());
+        let (mut run, mut weight) = (0usize, 1usize);
+
+        for c in block {
+            if c == RUN_A || c == RUN_B {
+                run += weight * (c as usize + 1);
+                weight *= 2;
+                if run > max_len {
+                    return Err(invalid_data("Run of zeros is too long"))
+                }
+                continue
+            }
+
+            decoded.resize(decoded.len() + run, 0);
+            (run, weight) = (0, 1);
+            decoded.push(c - 1);
+
+            if decoded.len() > max_len {
+                return Err(invalid_data("Block decodes to too many symbols"))
+            }
+        }
+        decoded.resize(decoded.len() + run, 0);
+
+        if decoded.len() > max_len {
+            return Err(invalid_data("Block decodes to too many symbols"))
+        }
+        Ok(decoded)
+    }
+}
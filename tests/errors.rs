@@ -0,0 +1,188 @@
+//! Bad input gives an error, never a panic: empty, truncated or corrupted
+//! files, and files of another format or version.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_huffman_egui::archive::{self, ArchiveReader};
+use rust_huffman_egui::container::FORMAT_VERSION;
+use rust_huffman_egui::{CodingMethod, Huffman, HuffmanError, HuffmanOptions, CODERS};
+
+/// An empty directory of its own for a test to write to.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust-huffman-egui-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn sample() -> Vec<u8> {
+    fs::read("examples/human-rights-es.txt").unwrap()[..2000].to_vec()
+}
+
+/// `sample` followed by runs of four, five and more than 255 equal bytes,
+/// so that run-length coding has counts to code.
+fn sample_with_runs() -> Vec<u8> {
+    let mut original = sample();
+    for (b, len) in [(b'=', 4), (b'-', 5), (b' ', 300)] {
+        original.extend(std::iter::repeat_n(b, len));
+        original.push(b'\n');
+    }
+    original
+}
+
+/// `sample_with_runs` compressed with every coder and method, with and
+/// without shortening runs.
+fn compressed_samples() -> Vec<(String, Vec<u8>)> {
+    let original = sample_with_runs();
+    let mut compressed = Vec::new();
+    for coder in CODERS {
+        for method in coder.methods().iter().chain([&CodingMethod::Stored]) {
+            for run_length in [false, true] {
+                let options = HuffmanOptions { coder: *coder, method: *method, run_length, ..Default::default() };
+                compressed.push((format!("{} ({method:?}, run length {run_length})", coder.name()),
+                                 Huffman::compress_bytes(&original, options).unwrap()));
+            }
+        }
+    }
+    compressed
+}
+
+#[test]
+fn empty_input_is_rejected() {
+    assert!(matches!(Huffman::compress_bytes(&[], HuffmanOptions::default()), Err(HuffmanError::EmptyInput)));
+
+    let dir = scratch_dir("empty");
+    let empty = dir.join("empty.txt");
+    fs::write(&empty, "").unwrap();
+    let filepath = empty.to_str().unwrap().to_string();
+    assert!(matches!(Huffman::from_file(&filepath, HuffmanOptions::default()), Err(HuffmanError::EmptyInput)));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn other_formats_are_rejected() {
+    let err = Huffman::decompress_bytes(b"Plain text, not compressed").unwrap_err();
+    assert!(matches!(err, HuffmanError::Format(_)), "{err:?}");
+
+    let mut compressed = Huffman::compress_bytes(&sample(), HuffmanOptions::default()).unwrap();
+    compressed[4] = FORMAT_VERSION + 1;
+    let err = Huffman::decompress_bytes(&compressed).unwrap_err();
+    assert!(matches!(err, HuffmanError::UnsupportedVersion { found, supported }
+                     if found == FORMAT_VERSION + 1 && supported == FORMAT_VERSION), "{err:?}");
+
+    // A flag this version does not know, in the high byte of the flags
+    let mut compressed = Huffman::compress_bytes(&sample(), HuffmanOptions::default()).unwrap();
+    compressed[7] |= 1;
+    let err = Huffman::decompress_bytes(&compressed).unwrap_err();
+    assert!(matches!(err, HuffmanError::Format(_)), "{err:?}");
+}
+
+#[test]
+fn truncated_streams_are_rejected() {
+    for (name, compressed) in compressed_samples() {
+        for len in 0..compressed.len() {
+            assert!(Huffman::decompress_bytes(&compressed[..len]).is_err(),
+                    "{name} cut to {len} bytes decompresses");
+        }
+    }
+}
+
+#[test]
+fn checksum_mismatch_is_corruption() {
+    for (name, mut compressed) in compressed_samples() {
+        *compressed.last_mut().unwrap() ^= 1;
+        let err = Huffman::decompress_bytes(&compressed).unwrap_err();
+        assert!(matches!(err, HuffmanError::Corrupted(_)), "{name}: {err:?}");
+    }
+}
+
+#[test]
+fn flipped_bits_never_panic() {
+    let original = sample_with_runs();
+    for (name, compressed) in compressed_samples() {
+        // The header and first code tables, where a bit flip can make code
+        // lengths over-subscribed
+        for bit in 0..(compressed.len() * 8).min(1024) {
+            let mut corrupted = compressed.clone();
+            corrupted[bit / 8] ^= 1 << (bit % 8);
+            // Padding bits change nothing
+            if let Ok(decompressed) = Huffman::decompress_bytes(&corrupted) {
+                assert!(decompressed == original, "{name} with bit {bit} flipped decompresses wrong");
+            }
+        }
+    }
+}
+
+#[test]
+fn corrupted_file_leaves_nothing_behind() {
+    let dir = scratch_dir("corrupted-file");
+    let mut compressed = Huffman::compress_bytes(&sample(), HuffmanOptions::default()).unwrap();
+    // Bit 0 of byte 20 is in the code lengths of the first block
+    compressed[20] ^= 1;
+    let compressed_path = dir.join("sample.txt.huff");
+    fs::write(&compressed_path, compressed).unwrap();
+
+    assert!(Huffman::decompress(compressed_path.to_str().unwrap().to_string(), false).is_err());
+    let left: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(left, ["sample.txt.huff"]);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+fn archive_of(dir: &Path, options: HuffmanOptions, share_code: bool) -> Vec<u8> {
+    let tree = dir.join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("sample.txt"), sample()).unwrap();
+    fs::write(tree.join("sub").join("simple.txt"), "simple text").unwrap();
+
+    let archive_path = dir.join("tree.huffa");
+    archive::create_archive(&tree, &archive_path, options, share_code).unwrap();
+    fs::remove_dir_all(tree).unwrap();
+    let archive = fs::read(&archive_path).unwrap();
+    fs::remove_file(archive_path).unwrap();
+    archive
+}
+
+#[test]
+fn corrupted_archives_are_rejected() {
+    let dir = scratch_dir("corrupted-archive");
+    let archive_path = dir.join("corrupted.huffa");
+    let extracted = dir.join("extracted");
+
+    for share_code in [false, true] {
+        let archive = archive_of(&dir, HuffmanOptions::default(), share_code);
+
+        let mut wrong_magic = archive.clone();
+        wrong_magic[0] ^= 1;
+        fs::write(&archive_path, wrong_magic).unwrap();
+        assert!(matches!(ArchiveReader::open(&archive_path), Err(HuffmanError::Format(_))));
+
+        let mut wrong_version = archive.clone();
+        wrong_version[4] += 1;
+        fs::write(&archive_path, wrong_version).unwrap();
+        assert!(matches!(ArchiveReader::open(&archive_path), Err(HuffmanError::UnsupportedVersion { .. })));
+
+        for len in (0..archive.len()).step_by(7) {
+            fs::write(&archive_path, &archive[..len]).unwrap();
+            fs::create_dir_all(&extracted).unwrap();
+            let extract = ArchiveReader::open(&archive_path)
+                .and_then(|reader| reader.extract_all(&extracted, false));
+            assert!(extract.is_err(), "Archive cut to {len} bytes extracts");
+            fs::remove_dir_all(&extracted).unwrap();
+        }
+
+        for bit in (0..archive.len() * 8).step_by(5) {
+            let mut corrupted = archive.clone();
+            corrupted[bit / 8] ^= 1 << (bit % 8);
+            fs::write(&archive_path, corrupted).unwrap();
+            fs::create_dir_all(&extracted).unwrap();
+            // Only that it returns, whatever it returns
+            let _ = ArchiveReader::open(&archive_path).and_then(|reader| reader.extract_all(&extracted, false));
+            fs::remove_dir_all(&extracted).unwrap();
+        }
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,70 @@
+//! Gzip output, checked by decompressing it with the system `gzip`.
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use rust_huffman_egui::{Huffman, HuffmanOptions};
+
+/// What `gzip -d` makes of `compressed`, or `None` when there is no `gzip`
+/// to run.
+fn system_gunzip(compressed: Vec<u8>) -> Option<Vec<u8>> {
+    let mut gzip = match Command::new("gzip").arg("-dc")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn() {
+        Ok(gzip) => gzip,
+        Err(err) => {
+            eprintln!("Skipping, gzip could not be run: {err}");
+            return None
+        }
+    };
+
+    let mut stdin = gzip.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(&compressed));
+    let output = gzip.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+
+    assert!(output.status.success(), "gzip failed: {}", String::from_utf8_lossy(&output.stderr));
+    Some(output.stdout)
+}
+
+fn inputs() -> Vec<(String, Vec<u8>)> {
+    let mut inputs: Vec<(String, Vec<u8>)> = ["simple.txt", "human-rights-es.txt", "romeo-and-juliet.txt"].iter()
+        .map(|name| (name.to_string(), fs::read(format!("examples/{name}")).unwrap()))
+        .collect();
+
+    let romeo = inputs[2].1.clone();
+    inputs.push(("empty".to_string(), Vec::new()));
+    inputs.push(("one byte".to_string(), vec![b'x']));
+    inputs.push(("zeros".to_string(), vec![0; 300_000]));
+    // Random bytes, which only stored blocks keep from growing much
+    let mut x = 0x2545_F491_4F6C_DD1Du64;
+    inputs.push(("random".to_string(), (0..200_000).map(|_| {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        (x >> 24) as u8
+    }).collect()));
+    // More than one block
+    inputs.push(("romeo nine times".to_string(), romeo.repeat(9)));
+
+    inputs
+}
+
+#[test]
+fn system_gzip_reads_our_gzip_output() {
+    for (name, original) in inputs() {
+        for max_code_length in [None, Some(9)] {
+            let options = HuffmanOptions { max_code_length, ..Default::default() };
+            let compressed = Huffman::gzip_bytes(&original, options).unwrap();
+            assert!(compressed.len() <= original.len() + original.len() / 1000 + 64,
+                    "{name} grew to {} bytes", compressed.len());
+
+            let Some(decompressed) = system_gunzip(compressed) else {
+                return
+            };
+            assert!(decompressed == original, "{name} ({max_code_length:?}) does not decompress to the original");
+        }
+    }
+}
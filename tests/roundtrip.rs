@@ -0,0 +1,210 @@
+//! Round trips over the files in `examples`, with every coder and method.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rust_huffman_egui::{CodingMethod, Huffman, HuffmanError, HuffmanOptions, SymbolMode, CODERS};
+
+fn examples(extension: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir("examples").expect("Running from the repository root")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No .{extension} files in examples");
+    paths
+}
+
+/// Runs of every length around the edges of run-length coding: shorter
+/// than four, exactly four and five, and longer than one count can hold.
+fn runs(symbols: &[u8]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    for (i, len) in [1, 2, 3, 4, 5, 6, 254, 255, 256, 259, 260, 1000, 4, 5].into_iter().enumerate() {
+        runs.extend(std::iter::repeat_n(symbols[i % symbols.len()], len));
+    }
+    runs.repeat(3)
+}
+
+fn round_trips(name: &str, original: &[u8]) {
+    for coder in CODERS {
+        let methods = coder.methods().iter().chain([&CodingMethod::Stored]);
+        for method in methods {
+            for run_length in [false, true] {
+                let options = HuffmanOptions { coder: *coder, method: *method, run_length, ..Default::default() };
+                let compressed = Huffman::compress_bytes(original, options).unwrap();
+                let decompressed = Huffman::decompress_bytes(&compressed).unwrap();
+
+                assert!(decompressed == original,
+                        "{name} with {} ({method:?}, run length {run_length}) does not round trip", coder.name());
+            }
+        }
+    }
+}
+
+#[test]
+fn every_coder_round_trips_the_examples() {
+    // The compressed examples are binary input
+    let inputs = examples("txt").into_iter().chain(examples("huff"));
+
+    for path in inputs {
+        round_trips(&path.display().to_string(), &fs::read(&path).unwrap());
+    }
+}
+
+#[test]
+fn every_coder_round_trips_runs() {
+    round_trips("text runs", &runs(b"ab \n"));
+    // Not UTF-8, and with runs of the symbols counts are coded as
+    round_trips("binary runs", &runs(&[0, 1, 4, 0xFF, 251]));
+}
+
+#[test]
+fn compressed_examples_decompress_to_the_originals() {
+    for path in examples("huff") {
+        let compressed = fs::read(&path).unwrap();
+        let original = fs::read(path.with_extension("")).unwrap();
+
+        assert!(Huffman::decompress_bytes(&compressed).unwrap() == original,
+                "{} does not decompress to the original", path.display());
+    }
+}
+
+/// Text with characters past U+00FF: curly quotes, the euro sign, CJK and
+/// emoji, some of them four bytes long in UTF-8.
+fn wide_text() -> Vec<u8> {
+    "“Ça coûte 5 €”, dit-elle. 東京は雨です。🎉🦀 Ünïcödé ‘quotes’ and ⟨brackets⟩ 😀😀😀\n"
+        .repeat(40)
+        .into_bytes()
+}
+
+#[test]
+fn wide_characters_are_read_as_text() {
+    let huffman = Huffman::from_reader(&wide_text()[..], HuffmanOptions::default()).unwrap();
+    assert_eq!(huffman.mode(), SymbolMode::Text);
+}
+
+#[test]
+fn every_coder_round_trips_wide_characters() {
+    round_trips("wide characters", &wide_text());
+}
+
+/// An empty directory of its own for a test to write to.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust-huffman-egui-roundtrip-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(unix)]
+#[test]
+fn metadata_is_restored_only_when_asked() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let dir = scratch_dir("metadata");
+    let original = dir.join("original.txt");
+    let contents = fs::read("examples/simple.txt").unwrap();
+    fs::write(&original, &contents).unwrap();
+    let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::options().write(true).open(&original).unwrap().set_modified(modified).unwrap();
+    fs::set_permissions(&original, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let options = HuffmanOptions { store_metadata: true, ..Default::default() };
+    let filepath = original.to_str().unwrap().to_string();
+    let (huffman, _) = Huffman::from_file(&filepath, options).unwrap();
+    let (compressed, _) = huffman.compress(filepath, options).unwrap();
+    // Under another name, so that the recorded one is not the one derived
+    let renamed = dir.join("renamed.huff");
+    fs::rename(compressed, &renamed).unwrap();
+    let renamed = renamed.to_str().unwrap().to_string();
+
+    // Not over the original, which is still there
+    let err = Huffman::decompress(renamed.clone(), true).unwrap_err();
+    assert!(matches!(&err, HuffmanError::Io(err) if err.kind() == io::ErrorKind::AlreadyExists), "{err:?}");
+    assert_eq!(fs::read(&original).unwrap(), contents);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2, "Decompressing left files behind");
+
+    fs::remove_file(&original).unwrap();
+    let restored = Huffman::decompress(renamed.clone(), true).unwrap();
+    assert_eq!(Path::new(&restored), original);
+    let metadata = fs::metadata(&restored).unwrap();
+    assert_eq!(fs::read(&restored).unwrap(), contents);
+    assert_eq!(metadata.modified().unwrap(), modified);
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+
+    let plain = Huffman::decompress(renamed, false).unwrap();
+    assert_eq!(Path::new(&plain), dir.join("renamed"));
+    let metadata = fs::metadata(&plain).unwrap();
+    assert_eq!(fs::read(&plain).unwrap(), contents);
+    assert_ne!(metadata.modified().unwrap(), modified);
+    // The permissions of any new file, whatever the umask
+    let fresh = dir.join("fresh");
+    fs::write(&fresh, "").unwrap();
+    assert_eq!(metadata.permissions().mode(), fs::metadata(fresh).unwrap().permissions().mode());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn output_does_not_depend_on_threads() {
+    // More blocks than one batch of 8 threads codes
+    let original = fs::read("examples/romeo-and-juliet.txt").unwrap().repeat(4);
+
+    let compress = |threads| Huffman::compress_bytes(&original, HuffmanOptions { threads, ..Default::default() }).unwrap();
+    let compressed = compress(1);
+    assert!(compress(8) == compressed, "Coded on 8 threads, the output differs from 1");
+    assert!(Huffman::decompress_bytes(&compressed).unwrap() == original);
+}
+
+#[test]
+fn memory_and_file_apis_agree() {
+    let dir = scratch_dir("parity");
+
+    for path in examples("txt") {
+        let original = fs::read(&path).unwrap();
+        let copy = dir.join(path.file_name().unwrap());
+        fs::write(&copy, &original).unwrap();
+
+        let options = HuffmanOptions::default();
+        let filepath = copy.to_str().unwrap().to_string();
+        let (huffman, _) = Huffman::from_file(&filepath, options).unwrap();
+        let (compressed_filepath, _) = huffman.compress(filepath, options).unwrap();
+        let from_file = fs::read(&compressed_filepath).unwrap();
+        let in_memory = Huffman::compress_bytes(&original, options).unwrap();
+
+        assert!(from_file == in_memory, "{} compresses differently in memory", path.display());
+        assert!(Huffman::decompress_bytes(&from_file).unwrap() == original);
+
+        // What was compressed in memory, decompressed from a file
+        fs::remove_file(&copy).unwrap();
+        fs::write(&compressed_filepath, &in_memory).unwrap();
+        let decompressed = Huffman::decompress(compressed_filepath, false).unwrap();
+        assert!(fs::read(decompressed).unwrap() == original);
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// The most a short input grows by, stored as is: 8 bytes of header, 3 of
+/// framing around fewer than 128 bytes, and the 4 of the CRC.
+const SHORT_INPUT_OVERHEAD: usize = 8 + 3 + 4;
+
+#[test]
+fn short_inputs_grow_by_the_overhead_at_most() {
+    for len in 1..128 {
+        let original: Vec<u8> = (0..len).map(|i| (i * 89 % 251) as u8).collect();
+        let compressed = Huffman::compress_bytes(&original, HuffmanOptions::default()).unwrap();
+        assert!(
+            compressed.len() <= len + SHORT_INPUT_OVERHEAD,
+            "{len} bytes compress to {}",
+            compressed.len()
+        );
+    }
+
+    let simple = fs::read("examples/simple.txt").unwrap();
+    let compressed = Huffman::compress_bytes(&simple, HuffmanOptions::default()).unwrap();
+    assert_eq!(compressed.len(), simple.len() + SHORT_INPUT_OVERHEAD);
+    assert_eq!(fs::read("examples/simple.txt.huff").unwrap(), compressed);
+}